@@ -48,10 +48,23 @@ impl ColorConfig {
     }
 }
 
+/// Settings for how source lines are displayed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// The maximum number of characters of a source line to display.
+    ///
+    /// Longer lines are truncated, with `…` marking the text that was cut off. If the first
+    /// annotation on a line would be cut off, the line is also scrolled horizontally so that
+    /// the annotation is visible, and annotations that still don't fit point at the `…`.
+    /// `None` displays lines in full.
+    pub max_width: Option<usize>,
+}
+
 /// Formats and prints diagnostic messages.
 pub struct Emitter<'a, T: FileData> {
     dst: Destination<'a>,
     cm: Option<&'a CodeMap<T>>,
+    options: RenderOptions,
 }
 
 struct FileWithAnnotatedLines<T: FileData> {
@@ -67,6 +80,7 @@ impl<'a, T: FileData> Emitter<'a, T> {
         Self {
             dst,
             cm: code_map,
+            options: RenderOptions::default(),
         }
     }
 
@@ -75,6 +89,7 @@ impl<'a, T: FileData> Emitter<'a, T> {
         Self {
             dst: Raw(Box::new(vec)),
             cm: code_map,
+            options: RenderOptions::default(),
         }
     }
 
@@ -83,9 +98,16 @@ impl<'a, T: FileData> Emitter<'a, T> {
         Emitter {
             dst: Raw(dst),
             cm: code_map,
+            options: RenderOptions::default(),
         }
     }
 
+    /// Sets the options used to display source lines.
+    pub fn with_render_options(mut self, options: RenderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
     fn preprocess_annotations(cm: Option<&'a CodeMap<T>>, spans: &[SpanLabel]) -> Vec<FileWithAnnotatedLines<T>> {
         fn add_annotation_to_file<'a, T: FileData>(file_vec: &mut Vec<FileWithAnnotatedLines<T>>,
                                   file: Arc<File<T>>,
@@ -220,7 +242,14 @@ impl<'a, T: FileData> Emitter<'a, T> {
                           width_offset: usize,
                           code_offset: usize) -> Vec<(usize, Style)> {
 
-        let source_string = file.source_line(line.line_index);
+        let truncated;
+        let (source_string, line) = match self.options.max_width {
+            Some(max_width) => {
+                truncated = truncate_line(file.source_line(line.line_index), line, max_width);
+                (&truncated.0[..], &truncated.1)
+            }
+            None => (file.source_line(line.line_index), line),
+        };
 
         let line_offset = buffer.num_lines();
 
@@ -786,8 +815,7 @@ impl<'a, T: FileData> Emitter<'a, T> {
                 // Then, the secondary file indicator
                 buffer.prepend(buffer_msg_line_offset + 1, "::: ", Style::LineNumber);
                 buffer.append(buffer_msg_line_offset + 1,
-                              // TODO: maybe make it so it doesn't have to convert to string
-                              &annotated_file.file.name().to_string(),
+                              &annotated_file.file.name_string(),
                               Style::LineAndColumn);
                 for _ in 0..max_line_num_len {
                     buffer.prepend(buffer_msg_line_offset + 1, " ", Style::NoStyle);
@@ -857,8 +885,13 @@ impl<'a, T: FileData> Emitter<'a, T> {
                                                 *style);
                         }
                     } else if line_idx_delta == 2 {
-                        let unannotated_line = annotated_file.file
-                            .source_line(annotated_file.lines[line_idx].line_index);
+                        let mut unannotated_line = annotated_file.file
+                            .source_line(annotated_file.lines[line_idx].line_index)
+                            .to_owned();
+                        if let Some(max_width) = self.options.max_width {
+                            let line = Line { line_index: 0, annotations: vec![] };
+                            unannotated_line = truncate_line(&unannotated_line, &line, max_width).0;
+                        }
 
                         let last_buffer_line_num = buffer.num_lines();
 
@@ -957,6 +990,56 @@ fn draw_multiline_line(buffer: &mut StyledBuffer,
     buffer.putc(line, offset + depth - 1, '|', style);
 }
 
+/// Truncates a source line to at most `max_width` characters for display, as described on
+/// `RenderOptions::max_width`, and adjusts the columns of the line's annotations to match.
+fn truncate_line(source: &str, line: &Line, max_width: usize) -> (String, Line) {
+    let len = source.chars().count();
+    // Too narrow to show both markers and some text.
+    if len <= max_width || max_width < 3 {
+        return (source.to_owned(), line.clone());
+    }
+
+    let first_col = line.annotations.iter()
+        .filter(|a| !a.is_line())
+        .map(|a| a.start_col)
+        .min()
+        .unwrap_or(0);
+    // The number of characters hidden on the left, and whether a leading `…` replaces them.
+    let (skip, lead) = if first_col + 1 < max_width {
+        (0, 0)
+    } else {
+        (first_col - max_width / 4, 1)
+    };
+    let (shown, trail) = if len - skip <= max_width - lead {
+        (len - skip, 0)
+    } else {
+        (max_width - lead - 1, 1)
+    };
+
+    let mut text = String::with_capacity(source.len());
+    if lead == 1 {
+        text.push('…');
+    }
+    text.extend(source.chars().skip(skip).take(shown));
+    if trail == 1 {
+        text.push('…');
+    }
+
+    // Annotations past the visible text point at the trailing `…`.
+    let visible_end = lead + shown;
+    let (start_limit, end_limit) = if trail == 1 {
+        (visible_end, visible_end + 1)
+    } else {
+        (usize::MAX, usize::MAX)
+    };
+    let mut line = line.clone();
+    for ann in line.annotations.iter_mut().filter(|a| !a.is_line()) {
+        ann.start_col = min(ann.start_col.saturating_sub(skip) + lead, start_limit);
+        ann.end_col = min(ann.end_col.saturating_sub(skip) + lead, end_limit);
+    }
+    (text, line)
+}
+
 fn num_overlap(a_start: usize, a_end: usize, b_start: usize, b_end:usize, inclusive: bool) -> bool {
     let extra = if inclusive {
         1
@@ -1140,3 +1223,102 @@ impl<'a, 'b> Drop for WritableDst<'a, 'b> {
         }
     }
 }
+
+#[test]
+fn test_primary_and_secondary_underlines() {
+    use codemap2::DefaultFileData;
+
+    let mut codemap = CodeMap::new();
+    let file_span = codemap
+        .add_file(DefaultFileData::new("test.rs".to_owned(), "let x = foo(bar);\n".to_owned()))
+        .span;
+    let diagnostic = Diagnostic {
+        level: Level::Error,
+        message: "mismatched types".to_owned(),
+        code: None,
+        spans: vec![
+            SpanLabel {
+                span: file_span.subspan(12, 15),
+                style: SpanStyle::Primary,
+                label: Some("expected `u32`".to_owned()),
+            },
+            SpanLabel {
+                span: file_span.subspan(8, 16),
+                style: SpanStyle::Secondary,
+                label: Some("in this call".to_owned()),
+            },
+        ],
+    };
+
+    let mut out = vec![];
+    Emitter::vec(&mut out, Some(&codemap)).emit(&[diagnostic]);
+    assert_eq!(String::from_utf8(out).unwrap(), "\
+error: mismatched types
+ --> test.rs:1:13
+  |
+1 | let x = foo(bar);
+  |         ----^^^-
+  |         |   |
+  |         |   expected `u32`
+  |         in this call
+
+");
+}
+
+#[test]
+fn test_render_options_max_width() {
+    use codemap2::DefaultFileData;
+
+    let mut codemap = CodeMap::new();
+    let source = "let result = compute(first_argument, second_argument, third_argument);\n";
+    let file_span = codemap
+        .add_file(DefaultFileData::new("test.rs".to_owned(), source.to_owned()))
+        .span;
+    let diagnostic = |begin, end| Diagnostic {
+        level: Level::Error,
+        message: "unknown value".to_owned(),
+        code: None,
+        spans: vec![SpanLabel {
+            span: file_span.subspan(begin, end),
+            style: SpanStyle::Primary,
+            label: Some("here".to_owned()),
+        }],
+    };
+    let render = |diagnostic| {
+        let mut out = vec![];
+        Emitter::vec(&mut out, Some(&codemap))
+            .with_render_options(RenderOptions { max_width: Some(30) })
+            .emit(&[diagnostic]);
+        String::from_utf8(out).unwrap()
+    };
+
+    // The span is visible without scrolling, so only the end of the line is cut off.
+    assert_eq!(render(diagnostic(13, 20)), "\
+error: unknown value
+ --> test.rs:1:14
+  |
+1 | let result = compute(first_ar…
+  |              ^^^^^^^ here
+
+");
+
+    // The line is scrolled so that the span is visible.
+    assert_eq!(render(diagnostic(37, 52)), "\
+error: unknown value
+ --> test.rs:1:38
+  |
+1 | …ument, second_argument, thir…
+  |         ^^^^^^^^^^^^^^^ here
+
+");
+
+    // Spans past the visible text are cut off at, or point at, the trailing marker.
+    assert_eq!(render(diagnostic(13, 68)).lines().nth(4), Some("  |              ^^^^^^^^^^^^^^^^^ here"));
+    let mut two_spans = diagnostic(13, 20);
+    two_spans.spans.push(SpanLabel {
+        span: file_span.subspan(54, 68),
+        style: SpanStyle::Secondary,
+        label: None,
+    });
+    assert_eq!(render(two_spans).lines().nth(4), Some("  |              ^^^^^^^ here    -"));
+}
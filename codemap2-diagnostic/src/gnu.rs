@@ -0,0 +1,136 @@
+// Rendering of diagnostics in the `file:line:col: message` format used by GCC and Clang.
+
+use std::fmt::Write;
+
+use codemap2::{CodeMap, FileData};
+use {Diagnostic, SpanStyle};
+
+/// The tab stop width GCC uses when computing display columns.
+const TAB_WIDTH: usize = 8;
+
+impl Diagnostic {
+    /// Renders the diagnostic in the GNU format understood by editors that parse GCC/Clang output.
+    ///
+    /// The output is a `file:line:col: level: message` header, followed by the source line and a
+    /// caret line pointing at the primary span (or the first span if none is primary). Lines and
+    /// columns are 1-indexed, and columns count display cells with tabs expanded to a tab stop of
+    /// 8, as GCC does. Tabs in the echoed source are expanded likewise so that the space-indented
+    /// caret lines up. A diagnostic without spans renders as just `level: message`.
+    ///
+    /// # Panics
+    ///
+    ///   * If the span is not part of `codemap`.
+    pub fn render_gnu<T: FileData>(&self, codemap: &CodeMap<T>) -> String {
+        let mut out = String::new();
+        let span = self
+            .spans
+            .iter()
+            .find(|s| s.style == SpanStyle::Primary)
+            .or_else(|| self.spans.first())
+            .map(|s| s.span);
+
+        let span = match span {
+            Some(span) => span,
+            None => {
+                write_message(&mut out, self);
+                return out;
+            }
+        };
+
+        let loc = codemap.look_up_span(span);
+        let line = loc.file.source_line(loc.begin.line);
+        let expanded = expand_tabs(line);
+        let begin_col = display_width(line.chars().take(loc.begin.column));
+        let end_col = if loc.end.line == loc.begin.line {
+            display_width(line.chars().take(loc.end.column))
+        } else {
+            expanded.chars().count()
+        };
+
+        let _ = write!(
+            out,
+            "{}:{}:{}: ",
+            loc.file.name(),
+            loc.begin.line + 1,
+            begin_col + 1
+        );
+        write_message(&mut out, self);
+        out.push_str(&expanded);
+        out.push('\n');
+        out.extend((0..begin_col).map(|_| ' '));
+        out.push('^');
+        out.extend((begin_col + 1..end_col).map(|_| '~'));
+        out.push('\n');
+        out
+    }
+}
+
+fn write_message(out: &mut String, diagnostic: &Diagnostic) {
+    let _ = write!(out, "{}: {}", diagnostic.level, diagnostic.message);
+    if let Some(ref code) = diagnostic.code {
+        let _ = write!(out, " [{}]", code);
+    }
+    out.push('\n');
+}
+
+/// The number of display cells taken by `chars` when starting at the beginning of a line.
+fn display_width<I: Iterator<Item = char>>(chars: I) -> usize {
+    chars.fold(0, |col, c| match c {
+        '\t' => col + TAB_WIDTH - col % TAB_WIDTH,
+        _ => col + 1,
+    })
+}
+
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    for c in line.chars() {
+        if c == '\t' {
+            let col = expanded.chars().count();
+            expanded.extend((0..TAB_WIDTH - col % TAB_WIDTH).map(|_| ' '));
+        } else {
+            expanded.push(c);
+        }
+    }
+    expanded
+}
+
+#[test]
+fn test_render_gnu() {
+    use codemap2::DefaultFileData;
+    use {Level, SpanLabel};
+
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "test.c".to_owned(),
+        "int main() {\n\tfoo();\n}\n".to_owned(),
+    ));
+    let diagnostic = Diagnostic {
+        level: Level::Error,
+        message: "implicit declaration of function `foo`".to_owned(),
+        code: None,
+        spans: vec![SpanLabel {
+            span: file.span.subspan(14, 15),
+            label: None,
+            style: SpanStyle::Primary,
+        }],
+    };
+
+    assert_eq!(
+        diagnostic.render_gnu(&codemap),
+        "test.c:2:9: error: implicit declaration of function `foo`\n        foo();\n        ^\n"
+    );
+
+    let diagnostic = Diagnostic {
+        spans: vec![SpanLabel {
+            span: file.span.subspan(14, 19),
+            label: None,
+            style: SpanStyle::Primary,
+        }],
+        code: Some("E001".to_owned()),
+        ..diagnostic
+    };
+    assert_eq!(
+        diagnostic.render_gnu(&codemap),
+        "test.c:2:9: error: implicit declaration of function `foo` [E001]\n        foo();\n        ^~~~~\n"
+    );
+}
@@ -42,8 +42,9 @@ mod lock;
 mod snippet;
 mod styled_buffer;
 mod emitter;
+mod gnu;
 
-pub use emitter::{ ColorConfig, Emitter };
+pub use emitter::{ ColorConfig, Emitter, RenderOptions };
 use termcolor::{ ColorSpec, Color };
 
 /// A diagnostic message.
@@ -139,3 +140,58 @@ pub enum SpanStyle {
     Primary,
     Secondary,
 }
+
+/// Groups diagnostics by the file containing their primary span.
+///
+/// The primary span is the first span with `SpanStyle::Primary`, or the first span if none is
+/// primary. Files appear in the order their first diagnostic does, and diagnostics keep their
+/// original order within each file. Diagnostics without any spans are not included.
+///
+/// # Panics
+///
+///   * If a span is not part of `codemap`.
+pub fn group_by_file<'d, T: FileData>(codemap: &CodeMap<T>, diags: &'d [Diagnostic])
+                                      -> Vec<(FileId, Vec<&'d Diagnostic>)> {
+    let mut groups: Vec<(FileId, Vec<&'d Diagnostic>)> = vec![];
+    for diag in diags {
+        let span = diag.spans.iter()
+            .find(|s| s.style == SpanStyle::Primary)
+            .or_else(|| diag.spans.first());
+        let id = match span {
+            Some(span_label) => codemap.find_file(span_label.span.low()).id(),
+            None => continue,
+        };
+        match groups.iter_mut().find(|group| group.0 == id) {
+            Some(group) => group.1.push(diag),
+            None => groups.push((id, vec![diag])),
+        }
+    }
+    groups
+}
+
+#[test]
+fn test_group_by_file() {
+    let mut codemap = CodeMap::new();
+    let foo = codemap.add_file(DefaultFileData::new("foo.rs".to_owned(), "a b c".to_owned()));
+    let bar = codemap.add_file(DefaultFileData::new("bar.rs".to_owned(), "d e".to_owned()));
+
+    let diag = |message: &str, span: Span, style: SpanStyle| Diagnostic {
+        level: Level::Error,
+        message: message.to_owned(),
+        code: None,
+        spans: vec![SpanLabel { span, label: None, style }],
+    };
+    let diags = vec![
+        diag("first", bar.span.subspan(0, 1), SpanStyle::Primary),
+        diag("second", foo.span.subspan(2, 3), SpanStyle::Primary),
+        diag("third", bar.span.subspan(2, 3), SpanStyle::Secondary),
+        Diagnostic { spans: vec![], ..diag("no span", foo.span, SpanStyle::Primary) },
+        diag("fourth", foo.span.subspan(0, 1), SpanStyle::Primary),
+    ];
+
+    let groups = group_by_file(&codemap, &diags);
+    assert_eq!(groups, vec![
+        (bar.id(), vec![&diags[0], &diags[2]]),
+        (foo.id(), vec![&diags[1], &diags[4]]),
+    ]);
+}
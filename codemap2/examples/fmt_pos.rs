@@ -0,0 +1,58 @@
+//! Times `CodeMap::fmt_pos` against formatting the `Loc` returned by `CodeMap::look_up_pos`,
+//! which clones the file's `Arc` for every lookup.
+//!
+//! Run with `cargo run --release --example fmt_pos`.
+extern crate codemap2;
+
+use codemap2::{CodeMap, DefaultFileData, Pos};
+use std::fmt;
+use std::time::Instant;
+
+const FILES: usize = 100;
+const LOOKUPS: usize = 200_000;
+
+/// Displays a position using `CodeMap::fmt_pos`.
+struct FmtPos<'a>(&'a CodeMap, Pos);
+
+impl<'a> fmt::Display for FmtPos<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        self.0.fmt_pos(self.1, f)
+    }
+}
+
+fn main() {
+    let mut codemap = CodeMap::new();
+    let files: Vec<_> = (0..FILES)
+        .map(|i| {
+            codemap.add_file(DefaultFileData::new(
+                format!("file{}.rs", i),
+                "let x = 1;\n".repeat(50),
+            ))
+        })
+        .collect();
+    let positions: Vec<_> = (0..LOOKUPS)
+        .map(|i| {
+            let file = &files[i % FILES];
+            file.span.low() + (i as u64 * 7) % file.span.len()
+        })
+        .collect();
+
+    let start = Instant::now();
+    let with_loc: Vec<_> = positions
+        .iter()
+        .map(|&pos| codemap.look_up_pos(pos).to_string())
+        .collect();
+    let loc_time = start.elapsed();
+
+    let start = Instant::now();
+    let with_fmt_pos: Vec<_> = positions
+        .iter()
+        .map(|&pos| FmtPos(&codemap, pos).to_string())
+        .collect();
+    let fmt_pos_time = start.elapsed();
+
+    assert_eq!(with_loc, with_fmt_pos);
+    println!("{} positions formatted across {} files:", LOOKUPS, FILES);
+    println!("  look_up_pos(..).to_string():  {:?}", loc_time);
+    println!("  CodeMap::fmt_pos:             {:?}", fmt_pos_time);
+}
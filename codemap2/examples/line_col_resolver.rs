@@ -0,0 +1,50 @@
+//! Times `LineColResolver` against repeated `File::find_line_col` calls on a long single-line
+//! file, where every lookup without the cache rescans the line from its start.
+//!
+//! Run with `cargo run --release --example line_col_resolver`.
+extern crate codemap2;
+
+use codemap2::{CodeMap, DefaultFileData};
+use std::time::Instant;
+
+const LINE_CHARS: usize = 200_000;
+const LOOKUPS: usize = 2_000;
+
+fn main() {
+    let source: String = "aé汉".chars().cycle().take(LINE_CHARS).collect();
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new("long.txt".to_owned(), source.clone()));
+
+    let step = source.len() / LOOKUPS;
+    let positions: Vec<_> = source
+        .char_indices()
+        .map(|(i, _)| i)
+        .filter(|i| i % step < 3)
+        .take(LOOKUPS)
+        .map(|i| file.span.low() + i as u64)
+        .collect();
+
+    let start = Instant::now();
+    let uncached: Vec<_> = positions
+        .iter()
+        .map(|&pos| file.find_line_col(pos))
+        .collect();
+    let uncached_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut resolver = file.line_col_resolver();
+    let cached: Vec<_> = positions
+        .iter()
+        .map(|&pos| resolver.find_line_col(pos))
+        .collect();
+    let cached_time = start.elapsed();
+
+    assert_eq!(uncached, cached);
+    println!(
+        "{} lookups on a {}-character line:",
+        positions.len(),
+        LINE_CHARS
+    );
+    println!("  File::find_line_col:             {:?}", uncached_time);
+    println!("  LineColResolver::find_line_col:  {:?}", cached_time);
+}
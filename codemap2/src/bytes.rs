@@ -0,0 +1,325 @@
+//! A byte-oriented counterpart to `CodeMap`, for sources that are not necessarily valid UTF-8.
+//!
+//! Lines are split on `\n` as in `CodeMap`, but every byte counts as one column.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use memchr::memchr_iter;
+use {BoxStr, FileId, LineCol, Pos, Span};
+
+/// A trait that represents file data whose source is arbitrary bytes.
+pub trait ByteFileData {
+    type Source: ?Sized + AsRef<[u8]> + PartialEq;
+    type Name: ?Sized + fmt::Display + fmt::Debug + PartialEq;
+
+    /// The full source bytes
+    fn source(&self) -> &Self::Source;
+
+    /// The human-readable identifier of the data (in most cases, the name)
+    fn name(&self) -> &Self::Name;
+}
+
+/// A default implementation of `ByteFileData` that owns its name and contents.
+#[derive(Debug)]
+pub struct DefaultByteFileData {
+    name: BoxStr,
+    contents: Box<[u8]>,
+}
+
+impl DefaultByteFileData {
+    pub fn new(name: String, contents: Vec<u8>) -> Self {
+        Self {
+            name: BoxStr::from_string(name),
+            contents: contents.into_boxed_slice(),
+        }
+    }
+}
+
+impl ByteFileData for DefaultByteFileData {
+    type Source = Box<[u8]>;
+    type Name = BoxStr;
+
+    fn source(&self) -> &Self::Source {
+        &self.contents
+    }
+
+    fn name(&self) -> &Self::Name {
+        &self.name
+    }
+}
+
+/// A `ByteCodeMap`'s record of a source file.
+pub struct ByteFile<T: ByteFileData> {
+    /// The span representing the entire file.
+    pub span: Span,
+
+    /// The identifier of the file within its `ByteCodeMap`.
+    id: FileId,
+
+    /// The data associated with a file
+    source: T,
+
+    /// Byte positions of line beginnings.
+    lines: Vec<Pos>,
+}
+
+impl<T: ByteFileData> Deref for ByteFile<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.source
+    }
+}
+
+impl<T: ByteFileData> ByteFile<T> {
+    /// Gets the identifier of the file within its `ByteCodeMap`.
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    /// Gets the line number of a Pos.
+    ///
+    /// The lines are 0-indexed (first line is numbered 0)
+    ///
+    /// # Panics
+    ///
+    ///  * If `pos` is not within this file's span
+    pub fn find_line(&self, pos: Pos) -> usize {
+        assert!(pos >= self.span.low());
+        assert!(pos <= self.span.high());
+        match self.lines.binary_search(&pos) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        }
+    }
+
+    /// Gets the line and column of a Pos, where the column is the byte offset within the line.
+    ///
+    /// # Panics
+    ///
+    /// * If `pos` is not with this file's span
+    pub fn find_line_col(&self, pos: Pos) -> LineCol {
+        let line = self.find_line(pos);
+        let column = (pos - self.lines[line]) as usize;
+        LineCol { line, column }
+    }
+
+    /// Gets the source bytes of a Span.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within this file.
+    pub fn source_slice(&self, span: Span) -> &[u8] {
+        assert!(self.span.contains(span));
+        &self.source().as_ref()
+            [((span.low() - self.span.low()) as usize)..((span.high() - self.span.low()) as usize)]
+    }
+
+    /// Gets the span representing a line by line number, including its line terminator.
+    ///
+    /// The line number is 0-indexed (first line is numbered 0).
+    ///
+    /// # Panics
+    ///
+    ///  * If the line number is out of range
+    pub fn line_span(&self, line: usize) -> Span {
+        assert!(line < self.lines.len());
+        Span::from(self.lines[line]..*self.lines.get(line + 1).unwrap_or(&self.span.high()))
+    }
+
+    /// Gets the source bytes of a line.
+    ///
+    /// The slice returned does not include the terminating \r or \n bytes.
+    ///
+    /// # Panics
+    ///
+    ///  * If the line number is out of range
+    pub fn source_line(&self, line: usize) -> &[u8] {
+        let mut text = self.source_slice(self.line_span(line));
+        while let Some((&b'\n', rest)) | Some((&b'\r', rest)) = text.split_last() {
+            text = rest;
+        }
+        text
+    }
+
+    /// Gets the number of lines in the file.
+    pub fn num_lines(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+impl<T: ByteFileData> fmt::Debug for ByteFile<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "ByteFile({:?})", self.name())
+    }
+}
+
+impl<T: ByteFileData> PartialEq for ByteFile<T> {
+    /// Compares by identity
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self as *const _, other as *const _)
+    }
+}
+
+impl<T: ByteFileData> Eq for ByteFile<T> {}
+
+impl<T: ByteFileData> Hash for ByteFile<T> {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.span.hash(hasher);
+    }
+}
+
+/// A file, and a line and byte column within it.
+#[derive(Eq, Debug)]
+pub struct ByteLoc<T: ByteFileData> {
+    pub file: Arc<ByteFile<T>>,
+    pub position: LineCol,
+}
+
+impl<T: ByteFileData> fmt::Display for ByteLoc<T> {
+    /// Formats the location as `filename:line:column`, with a 1-indexed
+    /// line and column.
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.file.name(),
+            self.position.line + 1,
+            self.position.column + 1
+        )
+    }
+}
+
+impl<T: ByteFileData> std::cmp::PartialEq for ByteLoc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.position == other.position && self.file == other.file
+    }
+}
+
+/// A data structure recording files of arbitrary bytes for position lookup.
+///
+/// This works like `CodeMap`, but never requires the sources to be valid UTF-8, and counts
+/// columns in bytes. Its positions are unrelated to those of any `CodeMap`.
+#[derive(Debug)]
+pub struct ByteCodeMap<T: ByteFileData = DefaultByteFileData> {
+    end_pos: Pos,
+    files: Vec<Arc<ByteFile<T>>>,
+}
+
+impl<T: ByteFileData> Default for ByteCodeMap<T> {
+    fn default() -> Self {
+        ByteCodeMap::new()
+    }
+}
+
+impl<T: ByteFileData> ByteCodeMap<T> {
+    /// Creates an empty `ByteCodeMap`.
+    pub fn new() -> Self {
+        ByteCodeMap {
+            end_pos: Pos(0),
+            files: vec![],
+        }
+    }
+
+    /// Adds a file with the given name and contents.
+    ///
+    /// Use the returned `ByteFile` and its `.span` property to create `Spans`
+    /// representing parts of the file.
+    pub fn add_file(&mut self, source: T) -> Arc<ByteFile<T>> {
+        let low = self.end_pos + 1;
+        let src = source.source().as_ref();
+        debug_assert!(
+            u64::from(low.0) + src.len() as u64 <= u64::from(u32::MAX),
+            "ByteCodeMap exceeded the 4GiB position space"
+        );
+        let high = low + src.len() as u64;
+        let mut lines = vec![low];
+        lines.extend(memchr_iter(b'\n', src).map(|i| low + (i + 1) as u64));
+
+        let file = Arc::new(ByteFile {
+            span: Span::from(low..high),
+            id: FileId(self.files.len() as u32),
+            source,
+            lines,
+        });
+        self.end_pos = high;
+        self.files.push(file.clone());
+        file
+    }
+
+    /// Looks up the `ByteFile` that contains the specified position, or returns `None` if `pos`
+    /// is not within any file.
+    pub fn try_find_file(&self, pos: Pos) -> Option<&Arc<ByteFile<T>>> {
+        self.files
+            .binary_search_by(|file| {
+                if file.span.high() < pos {
+                    Ordering::Less
+                } else if file.span.low() > pos {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .ok()
+            .map(|i| &self.files[i])
+    }
+
+    /// Looks up the `ByteFile` that contains the specified position.
+    ///
+    /// # Panics
+    ///
+    ///   * If `pos` is not within any file
+    pub fn find_file(&self, pos: Pos) -> &Arc<ByteFile<T>> {
+        self.try_find_file(pos)
+            .unwrap_or_else(|| panic!("Mapping unknown source location {:?}", pos))
+    }
+
+    /// Gets the file, line, and byte column represented by a `Pos`.
+    pub fn look_up_pos(&self, pos: Pos) -> ByteLoc<T> {
+        let file = self.find_file(pos);
+        ByteLoc {
+            file: file.clone(),
+            position: file.find_line_col(pos),
+        }
+    }
+}
+
+#[test]
+fn test_byte_codemap() {
+    let mut codemap = ByteCodeMap::new();
+    let a = codemap.add_file(DefaultByteFileData::new(
+        "a.bin".to_owned(),
+        b"mov \xff\xfe\r\n\x80x\nend".to_vec(),
+    ));
+    let b = codemap.add_file(DefaultByteFileData::new(
+        "b.bin".to_owned(),
+        b"\xc3".to_vec(),
+    ));
+    assert!(std::str::from_utf8(a.source()).is_err());
+
+    assert_eq!(a.num_lines(), 3);
+    assert_eq!(a.source_line(0), b"mov \xff\xfe");
+    assert_eq!(a.source_line(1), b"\x80x");
+    assert_eq!(a.source_line(2), b"end");
+    assert_eq!(a.source_slice(a.span.subspan(4, 6)), b"\xff\xfe");
+
+    let loc = codemap.look_up_pos(a.span.low() + 5);
+    assert_eq!(
+        (loc.file.id(), loc.position),
+        (a.id(), LineCol { line: 0, column: 5 })
+    );
+    assert_eq!(
+        codemap.look_up_pos(a.span.low() + 9).position,
+        LineCol { line: 1, column: 1 }
+    );
+    assert_eq!(codemap.look_up_pos(a.span.high()).to_string(), "a.bin:3:4");
+    assert_eq!(
+        codemap.look_up_pos(b.span.high()).position,
+        LineCol { line: 0, column: 1 }
+    );
+    assert_eq!(codemap.try_find_file(b.span.high() + 1), None);
+}
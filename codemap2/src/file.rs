@@ -1,6 +1,11 @@
 pub use super::*;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
-use std::ops::Deref;
+use std::iter;
+use std::ops::{Deref, RangeInclusive};
+use std::sync::OnceLock;
+use unicode_width::UnicodeWidthChar;
 
 /// A trait that represents file data
 pub trait FileData {
@@ -14,16 +19,31 @@ pub trait FileData {
     fn name(&self) -> &Self::Name;
 }
 
+/// An identifier for a file within a `CodeMap`.
+///
+/// Ids are assigned sequentially in the order files are added.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub struct FileId(pub u32);
+
 /// A `CodeMap`'s record of a source file.
 pub struct File<T: FileData> {
     /// The span representing the entire file.
     pub span: Span,
 
+    /// The identifier of the file within its `CodeMap`.
+    pub(crate) id: FileId,
+
     /// The data associated with a file
     pub(crate) source: T,
 
     /// Byte positions of line beginnings.
     pub(crate) lines: Vec<Pos>,
+
+    /// The widths of the longest line in characters and in display columns, computed on demand.
+    pub(crate) max_line_widths: OnceLock<(usize, usize)>,
+
+    /// The number of characters in the file, computed on demand.
+    pub(crate) total_chars: OnceLock<usize>,
 }
 
 impl<T: FileData> Deref for File<T> {
@@ -35,6 +55,52 @@ impl<T: FileData> Deref for File<T> {
 }
 
 impl<T: FileData> File<T> {
+    /// Gets the identifier of the file within its `CodeMap`.
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    /// Gets the name of the file as a `String`, formatted with `Display`.
+    pub fn name_string(&self) -> String {
+        self.name().to_string()
+    }
+
+    /// Gets the span representing the entire file.
+    pub fn full_span(&self) -> Span {
+        self.span
+    }
+
+    /// Makes a span from byte offsets relative to the start of this file.
+    ///
+    /// # Panics
+    ///   * If `end < begin`
+    ///   * If `end` is beyond the length of the file
+    pub fn subspan_local(&self, begin: usize, end: usize) -> Span {
+        self.span.subspan(begin as u64, end as u64)
+    }
+
+    /// Makes a span from a range of character (not byte) indices into the file's source, such as
+    /// one reported by a lexer that works in characters.
+    ///
+    /// Returns `None` if `end < start` or if `end` is beyond the number of characters in the file.
+    pub fn span_from_char_range(&self, start: usize, end: usize) -> Option<Span> {
+        if end < start {
+            return None;
+        }
+        let src = self.source().as_ref();
+        let mut offsets = src
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(iter::once(src.len()));
+        let begin = offsets.nth(start)?;
+        let end = if end == start {
+            begin
+        } else {
+            offsets.nth(end - start - 1)?
+        };
+        Some(self.subspan_local(begin, end))
+    }
+
     /// Gets the line number of a Pos.
     ///
     /// The lines are 0-indexed (first line is numbered 0)
@@ -51,6 +117,29 @@ impl<T: FileData> File<T> {
         }
     }
 
+    /// Gets the line number of a Pos, or returns `None` if `pos` is not within this file's span.
+    pub fn try_find_line(&self, pos: Pos) -> Option<usize> {
+        if pos < self.span.low || pos > self.span.high {
+            return None;
+        }
+        Some(self.find_line(pos))
+    }
+
+    /// Gets the line number of a Pos along with the span of that line, including its terminator
+    /// as in `line_span`.
+    ///
+    /// # Panics
+    ///
+    ///  * If `pos` is not within this file's span
+    pub fn find_line_span(&self, pos: Pos) -> (usize, Span) {
+        let line = self.find_line(pos);
+        let span = Span {
+            low: self.lines[line],
+            high: *self.lines.get(line + 1).unwrap_or(&self.span.high),
+        };
+        (line, span)
+    }
+
     /// Gets the line and column of a Pos.
     ///
     /// # Panics
@@ -68,6 +157,169 @@ impl<T: FileData> File<T> {
         LineCol { line, column }
     }
 
+    /// Gets the position just past the last character of the file.
+    pub fn eof_pos(&self) -> Pos {
+        self.span.high
+    }
+
+    /// Gets the line and column of the end of the file.
+    ///
+    /// If the file ends with a newline, this is column 0 of the empty line that follows it.
+    pub fn eof_line_col(&self) -> LineCol {
+        self.find_line_col(self.eof_pos())
+    }
+
+    /// Gets the line and column of a Pos, counting columns as specified by `mode`.
+    ///
+    /// # Panics
+    ///
+    /// * If `pos` is not with this file's span
+    /// * If `pos` points to a byte in the middle of a UTF-8 character
+    pub fn find_line_col_with_mode(&self, pos: Pos, mode: ColumnMode) -> LineCol {
+        match mode {
+            ColumnMode::Chars => self.find_line_col(pos),
+            ColumnMode::JoinedEmoji => {
+                let line = self.find_line(pos);
+                let line_span = self.line_span(line);
+                let byte_col = pos - line_span.low;
+                let mut joined = false;
+                let column = self.source_slice(line_span)[..byte_col as usize]
+                    .chars()
+                    .filter(|&c| {
+                        let starts_column =
+                            !joined && c != ZERO_WIDTH_JOINER && !is_variation_selector(c);
+                        joined = c == ZERO_WIDTH_JOINER;
+                        starts_column
+                    })
+                    .count();
+
+                LineCol { line, column }
+            }
+        }
+    }
+
+    /// Gets the line number of a Pos, the source text of that line, and the column of the Pos.
+    ///
+    /// The line text does not include the line terminator, and the column counts characters as
+    /// in `find_line_col`.
+    ///
+    /// # Panics
+    ///
+    /// * If `pos` is not with this file's span
+    /// * If `pos` points to a byte in the middle of a UTF-8 character
+    pub fn line_context(&self, pos: Pos) -> (usize, &str, usize) {
+        let LineCol { line, column } = self.find_line_col(pos);
+        (line, self.source_line(line), column)
+    }
+
+    /// Gets the line and column of a Pos, or returns `None` if `pos` is not within this file's
+    /// span or points to a byte in the middle of a UTF-8 character.
+    pub fn try_find_line_col(&self, pos: Pos) -> Option<LineCol> {
+        let line = self.try_find_line(pos)?;
+        let line_span = self.line_span(line);
+        let byte_col = pos - line_span.low;
+        let column = self
+            .source_slice(line_span)
+            .get(..byte_col as usize)?
+            .chars()
+            .count();
+
+        Some(LineCol { line, column })
+    }
+
+    /// Iterates over the start position of each character in a Span, paired with its line and
+    /// column.
+    ///
+    /// Only the start of the span is looked up; subsequent lines and columns are computed
+    /// incrementally, so iterating the whole span is O(n).
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within this file.
+    ///   * If `span` does not start on a UTF-8 character boundary.
+    pub fn line_cols_in(&self, span: Span) -> impl Iterator<Item = (Pos, LineCol)> + '_ {
+        let mut current = self.find_line_col(span.low);
+        self.source_slice(span).char_indices().map(move |(i, c)| {
+            let item = (span.low + i as u64, current);
+            if c == '\n' {
+                current.line += 1;
+                current.column = 0;
+            } else {
+                current.column += 1;
+            }
+            item
+        })
+    }
+
+    /// Gets the line and column of a Pos, with the column counted in UTF-16 code units as in the
+    /// Language Server Protocol.
+    ///
+    /// # Panics
+    ///
+    /// * If `pos` is not with this file's span
+    /// * If `pos` points to a byte in the middle of a UTF-8 character
+    pub fn find_line_col_utf16(&self, pos: Pos) -> LineCol {
+        let line = self.find_line(pos);
+        let line_span = self.line_span(line);
+        let byte_col = pos - line_span.low;
+        let column = self.source_slice(line_span)[..byte_col as usize]
+            .chars()
+            .map(char::len_utf16)
+            .sum();
+
+        LineCol { line, column }
+    }
+
+    /// Gets the Pos at a line and a column counted in UTF-16 code units, the inverse of
+    /// `find_line_col_utf16`.
+    ///
+    /// Returns `None` if the line is out of range, if the column is past the end of the line's
+    /// text, or if the column falls between the two halves of a surrogate pair.
+    pub fn pos_from_utf16(&self, line: usize, utf16_col: usize) -> Option<Pos> {
+        if line >= self.num_lines() {
+            return None;
+        }
+        let low = self.line_span(line).low;
+        let mut units = 0;
+        for (i, c) in self.source_line(line).char_indices() {
+            if units == utf16_col {
+                return Some(low + i as u64);
+            } else if units > utf16_col {
+                return None;
+            }
+            units += c.len_utf16();
+        }
+        if units == utf16_col {
+            Some(low + self.source_line(line).len() as u64)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the column of a Pos as displayed by an editor.
+    ///
+    /// Tabs advance to the next multiple of `tab_width`, and other characters take up their
+    /// display width, so wide characters such as CJK ideographs and emoji count as two columns
+    /// and combining characters count as zero. The column is 0-indexed.
+    ///
+    /// # Panics
+    ///
+    /// * If `tab_width` is 0
+    /// * If `pos` is not with this file's span
+    /// * If `pos` points to a byte in the middle of a UTF-8 character
+    pub fn editor_column(&self, pos: Pos, tab_width: usize) -> usize {
+        assert!(tab_width > 0);
+        let line_span = self.line_span(self.find_line(pos));
+        let prefix = self.source_slice(Span {
+            low: line_span.low,
+            high: pos,
+        });
+        prefix.chars().fold(0, |col, c| match c {
+            '\t' => col + tab_width - col % tab_width,
+            _ => col + c.width().unwrap_or(0),
+        })
+    }
+
     /// Gets the source text of a Span.
     ///
     /// # Panics
@@ -79,6 +331,211 @@ impl<T: FileData> File<T> {
             [((span.low - self.span.low) as usize)..((span.high - self.span.low) as usize)]
     }
 
+    /// Gets the source text of a Span, or returns `None` if `span` is not entirely within this
+    /// file or does not start and end on UTF-8 character boundaries.
+    pub fn try_source_slice(&self, span: Span) -> Option<&str> {
+        if !self.span.contains(span) {
+            return None;
+        }
+        self.source()
+            .as_ref()
+            .get(((span.low - self.span.low) as usize)..((span.high - self.span.low) as usize))
+    }
+
+    /// Gets the span of the first occurrence of `needle` in the file, if any.
+    pub fn find_str(&self, needle: &str) -> Option<Span> {
+        self.find_all_str(needle).next()
+    }
+
+    /// Iterates over the spans of all non-overlapping occurrences of `needle` in the file.
+    pub fn find_all_str<'a>(&'a self, needle: &'a str) -> impl Iterator<Item = Span> + 'a {
+        self.source()
+            .as_ref()
+            .match_indices(needle)
+            .map(move |(i, m)| self.span.subspan(i as u64, (i + m.len()) as u64))
+    }
+
+    /// Gets the positions of all occurrences of `ch` within a span.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within this file.
+    pub fn positions_of(&self, span: Span, ch: char) -> Vec<Pos> {
+        let text = self.source_slice(span);
+        if ch.is_ascii() {
+            memchr_iter(ch as u8, text.as_bytes())
+                .map(|i| span.low + i as u64)
+                .collect()
+        } else {
+            text.match_indices(ch)
+                .map(|(i, _)| span.low + i as u64)
+                .collect()
+        }
+    }
+
+    /// Gets the number of characters (not bytes) in the source text of a Span.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within this file.
+    pub fn char_count(&self, span: Span) -> usize {
+        self.source_slice(span).chars().count()
+    }
+
+    /// Gets the number of characters (not bytes) in the file.
+    ///
+    /// This is computed the first time it is needed and cached afterwards.
+    pub fn total_chars(&self) -> usize {
+        *self
+            .total_chars
+            .get_or_init(|| self.source().as_ref().chars().count())
+    }
+
+    /// Gets the source text of the part of a Span that lies within this file.
+    ///
+    /// Unlike `source_slice`, this never panics: the span is clamped to the file's bounds, an
+    /// empty string is returned if it doesn't overlap the file at all, and characters cut in half
+    /// by the span's ends are replaced with `U+FFFD REPLACEMENT CHARACTER`.
+    pub fn source_slice_lossy(&self, span: Span) -> Cow<'_, str> {
+        let low = cmp::max(span.low, self.span.low);
+        let high = cmp::min(span.high, self.span.high);
+        if low >= high {
+            return Cow::Borrowed("");
+        }
+        let bytes = self.source().as_ref().as_bytes();
+        String::from_utf8_lossy(
+            &bytes[((low - self.span.low) as usize)..((high - self.span.low) as usize)],
+        )
+    }
+
+    /// Widens a span so that both ends lie on UTF-8 character boundaries.
+    ///
+    /// `low` is moved down and `high` is moved up to the nearest boundary, so the result can be
+    /// passed to `source_slice` even if `span` came from byte-oriented tooling.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within this file.
+    pub fn snap_span(&self, span: Span) -> Span {
+        assert!(self.span.contains(span));
+        let source = self.source().as_ref();
+        let mut low = (span.low - self.span.low) as usize;
+        let mut high = (span.high - self.span.low) as usize;
+        while !source.is_char_boundary(low) {
+            low -= 1;
+        }
+        while !source.is_char_boundary(high) {
+            high += 1;
+        }
+        self.span.subspan(low as u64, high as u64)
+    }
+
+    /// Gets the smallest indentation of the lines touched by a Span, ignoring blank lines.
+    ///
+    /// The indentation of a line is the number of whitespace characters it begins with. Returns
+    /// 0 if every line is blank.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within this file.
+    pub fn common_indent(&self, span: Span) -> usize {
+        self.lines_touched(span)
+            .map(|line| self.source_line(line))
+            .filter(|text| !text.trim().is_empty())
+            .map(|text| text.chars().take_while(|c| c.is_whitespace()).count())
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Iterates over the spans of each paragraph: a run of consecutive lines that are not blank.
+    ///
+    /// A line is blank if it is empty or contains only whitespace. Each span begins at the start
+    /// of the paragraph's first line and ends before the line terminator of its last line.
+    pub fn paragraph_spans(&self) -> impl Iterator<Item = Span> + '_ {
+        let mut line = 0;
+        iter::from_fn(move || {
+            while line < self.num_lines() && self.is_blank_line(line) {
+                line += 1;
+            }
+            if line == self.num_lines() {
+                return None;
+            }
+            let first = line;
+            while line < self.num_lines() && !self.is_blank_line(line) {
+                line += 1;
+            }
+            Some(Span {
+                low: self.lines[first],
+                high: self.line_content_span(line - 1).high,
+            })
+        })
+    }
+
+    /// Iterates over the line numbers and spans of each blank line: one that is empty or
+    /// contains only whitespace.
+    ///
+    /// The spans exclude line terminators, as in `line_content_span`. As with `lines_opts(false)`,
+    /// the empty line after a trailing newline is not included.
+    pub fn blank_line_spans(&self) -> impl Iterator<Item = (usize, Span)> + '_ {
+        let count = self.lines_opts(false).count();
+        (0..count)
+            .filter(move |&line| self.is_blank_line(line))
+            .map(move |line| (line, self.line_content_span(line)))
+    }
+
+    fn is_blank_line(&self, line: usize) -> bool {
+        self.source_line(line).trim().is_empty()
+    }
+
+    /// The range of line numbers containing some part of `span`.
+    ///
+    /// For a non-empty span ending at the beginning of a line, that line is not included.
+    pub(crate) fn lines_touched(&self, span: Span) -> RangeInclusive<usize> {
+        let first = self.find_line(span.low);
+        let mut last = self.find_line(span.high);
+        if last > first && self.lines[last] == span.high {
+            last -= 1;
+        }
+        first..=last
+    }
+
+    /// Expands a span to cover every line it touches in full, including the last line's
+    /// terminator.
+    ///
+    /// A non-empty span ending at the beginning of a line does not touch that line.
+    ///
+    /// # Panics
+    ///
+    ///  * If `span` is not entirely within this file
+    pub fn expand_to_lines(&self, span: Span) -> Span {
+        let lines = self.lines_touched(span);
+        self.line_span(*lines.start())
+            .merge(self.line_span(*lines.end()))
+    }
+
+    /// Removes a trailing `\n` or `\r\n` from a span, if it ends with one.
+    ///
+    /// # Panics
+    ///
+    ///  * If `span` is not entirely within this file
+    pub fn exclude_terminator(&self, span: Span) -> Span {
+        let text = self.source_slice(span);
+        let trimmed = text
+            .strip_suffix('\n')
+            .map_or(text, |text| text.strip_suffix('\r').unwrap_or(text));
+        span.subspan(0, trimmed.len() as u64)
+    }
+
+    /// Gets the span of the whole lines enclosing all of `children`, or `None` if `children` is
+    /// empty.
+    ///
+    /// # Panics
+    ///
+    ///  * If any of `children` is not entirely within this file
+    pub fn enclosing_block_span(&self, children: &[Span]) -> Option<Span> {
+        Span::enclosing(children.iter().cloned()).map(|span| self.expand_to_lines(span))
+    }
+
     /// Gets the span representing a line by line number.
     ///
     /// The line number is 0-indexed (first line is numbered 0). The returned span includes the
@@ -95,6 +552,48 @@ impl<T: FileData> File<T> {
         }
     }
 
+    /// Gets the span of the first line, including its line terminator.
+    ///
+    /// For an empty file this is the file's single, empty line.
+    pub fn first_line_span(&self) -> Span {
+        self.line_span(0)
+    }
+
+    /// Gets the span of the last line.
+    ///
+    /// If the file ends in a newline, this is the empty line after it. For an empty file this is
+    /// the same as `first_line_span`.
+    pub fn last_line_span(&self) -> Span {
+        self.line_span(self.num_lines() - 1)
+    }
+
+    /// Gets the span representing a line by line number, with or without its line terminator.
+    ///
+    /// The line number is 0-indexed (first line is numbered 0).
+    ///
+    /// # Panics
+    ///
+    ///  * If the line number is out of range
+    pub fn line_span_kind(&self, line: usize, kind: LineSpanKind) -> Span {
+        match kind {
+            LineSpanKind::WithTerminator => self.line_span(line),
+            LineSpanKind::ContentOnly => self.line_content_span(line),
+        }
+    }
+
+    /// Gets the span representing the text of a line, excluding the line terminator.
+    ///
+    /// The line number is 0-indexed (first line is numbered 0). The returned span covers exactly
+    /// the text returned by `source_line`.
+    ///
+    /// # Panics
+    ///
+    ///  * If the line number is out of range
+    pub fn line_content_span(&self, line: usize) -> Span {
+        let span = self.line_span(line);
+        span.subspan(0, self.source_line(line).len() as u64)
+    }
+
     /// Gets the source text of a line.
     ///
     /// The string returned does not include the terminating \r or \n characters.
@@ -107,10 +606,282 @@ impl<T: FileData> File<T> {
             .trim_end_matches(&['\n', '\r'][..])
     }
 
+    /// Iterates over the source text of each line, without line terminators.
+    ///
+    /// This yields `num_lines()` items, so a file ending in a newline produces a final empty
+    /// line. Use `lines_opts` to leave it out.
+    pub fn lines(&self) -> impl Iterator<Item = &str> + '_ {
+        self.lines_opts(true)
+    }
+
+    /// Iterates over the source text of each line, without line terminators, along with the span
+    /// of that text as returned by `line_content_span`.
+    ///
+    /// Like `lines`, this yields `num_lines()` items.
+    pub fn spanned_lines(&self) -> impl Iterator<Item = Spanned<&str>> + '_ {
+        (0..self.num_lines()).map(move |line| Spanned {
+            node: self.source_line(line),
+            span: self.line_content_span(line),
+        })
+    }
+
+    /// Iterates over the source text of each line, without line terminators.
+    ///
+    /// If `include_trailing_empty` is false and the file ends in a newline, the empty line after
+    /// that newline is skipped, matching the convention of `str::lines`.
+    pub fn lines_opts(&self, include_trailing_empty: bool) -> impl Iterator<Item = &str> + '_ {
+        let mut count = self.num_lines();
+        if !include_trailing_empty && count > 1 && self.line_span(count - 1).is_empty() {
+            count -= 1;
+        }
+        (0..count).map(move |line| self.source_line(line))
+    }
+
+    /// Gets the source text with each line prefixed by its 1-indexed line number, for debug
+    /// output.
+    ///
+    /// Line numbers are right-aligned to the width of the largest one and separated from the
+    /// line by ` | `. Every line, including the last, ends with `\n`; the empty line after a
+    /// trailing newline is left out.
+    pub fn numbered_source(&self) -> String {
+        let count = self.lines_opts(false).count();
+        let width = count.to_string().len();
+        let mut out = String::new();
+        for (i, line) in self.lines_opts(false).enumerate() {
+            out.push_str(&format!("{:>width$} | {}\n", i + 1, line, width = width));
+        }
+        out
+    }
+
+    /// Checks whether the source text ends with `\n` (which includes ending with `\r\n`).
+    pub fn ends_with_newline(&self) -> bool {
+        self.source().as_ref().ends_with('\n')
+    }
+
     /// Gets the number of lines in the file
     pub fn num_lines(&self) -> usize {
         self.lines.len()
     }
+
+    /// Gets the number of characters in a line, excluding its line terminator.
+    ///
+    /// # Panics
+    ///
+    ///  * If the line number is out of range
+    pub fn line_char_width(&self, line: usize) -> usize {
+        self.source_line(line).chars().count()
+    }
+
+    /// Gets the display width of a line, excluding its line terminator, measuring characters as
+    /// in `max_line_display_width`.
+    ///
+    /// # Panics
+    ///
+    ///  * If the line number is out of range
+    pub fn line_display_width(&self, line: usize) -> usize {
+        self.source_line(line)
+            .chars()
+            .map(|c| c.width().unwrap_or(0))
+            .sum()
+    }
+
+    /// Gets the number of characters in the longest line, excluding line terminators.
+    ///
+    /// This is computed the first time it is needed and cached afterwards.
+    pub fn max_line_width(&self) -> usize {
+        self.max_line_widths().0
+    }
+
+    /// Gets the display width of the widest line, excluding line terminators.
+    ///
+    /// Characters are measured as in `editor_column`, except that tabs, like other control
+    /// characters, take up no columns.
+    /// This is computed the first time it is needed and cached afterwards.
+    pub fn max_line_display_width(&self) -> usize {
+        self.max_line_widths().1
+    }
+
+    fn max_line_widths(&self) -> (usize, usize) {
+        *self.max_line_widths.get_or_init(|| {
+            self.lines().fold((0, 0), |(chars, width), line| {
+                (
+                    cmp::max(chars, line.chars().count()),
+                    cmp::max(width, line.chars().map(|c| c.width().unwrap_or(0)).sum()),
+                )
+            })
+        })
+    }
+
+    /// Gets the positions of the beginnings of each line, in order.
+    pub fn line_starts(&self) -> &[Pos] {
+        &self.lines
+    }
+
+    /// Iterates over the 0-indexed line numbers paired with the position where each line begins.
+    pub fn line_starts_enumerated(&self) -> impl Iterator<Item = (usize, Pos)> + '_ {
+        self.line_starts().iter().cloned().enumerate()
+    }
+
+    /// Iterates over the positions of the `\n` bytes in the file.
+    ///
+    /// These are found by scanning the source, so for a file added with
+    /// `CodeMap::add_file_with_lines` they need not match the line table.
+    pub fn newline_positions(&self) -> impl Iterator<Item = Pos> + '_ {
+        let low = self.span.low;
+        memchr_iter(b'\n', self.source().as_ref().as_bytes()).map(move |i| low + i as u64)
+    }
+
+    /// Gets an identifier for this file derived only from its name and source text.
+    ///
+    /// Unlike the file's position in the `CodeMap`, this is the same across runs, machines, and
+    /// codemaps, so it can be used as a key in persistent caches. It is computed with 64-bit
+    /// FNV-1a, which is fast but not collision resistant against adversarial input.
+    pub fn stable_id(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let name = self.name_string();
+        // 0xff never occurs in UTF-8, so it unambiguously separates the name from the source
+        let bytes = name
+            .as_bytes()
+            .iter()
+            .chain(Some(&0xff))
+            .chain(self.source().as_ref().as_bytes());
+        bytes.fold(FNV_OFFSET_BASIS, |hash, &b| {
+            (hash ^ u64::from(b)).wrapping_mul(FNV_PRIME)
+        })
+    }
+
+    /// Creates a `LineColResolver` for resolving many positions within this file.
+    ///
+    /// The resolver caches the character boundaries of each line it has seen, so repeated
+    /// lookups on the same line are O(log n) instead of rescanning the line.
+    pub fn line_col_resolver(&self) -> LineColResolver<'_, T> {
+        LineColResolver {
+            file: self,
+            char_starts: HashMap::new(),
+        }
+    }
+
+    /// Creates a view of the part of this file covered by `span`, with positions relative to
+    /// the start of the span.
+    ///
+    /// This allows a region, such as the contents of a string literal, to be parsed on its own
+    /// while still being able to map positions back to the file.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within this file.
+    pub fn sub_file(&self, span: Span) -> SubFile<'_, T> {
+        let text = self.source_slice(span);
+        let mut lines = vec![Pos(0)];
+        lines.extend(memchr_iter(b'\n', text.as_bytes()).map(|i| Pos(i as u32 + 1)));
+        SubFile {
+            file: self,
+            span,
+            lines,
+        }
+    }
+}
+
+/// A view of part of a `File`, whose positions are relative to the start of that part.
+///
+/// Position `Pos(0)` is the start of the part, and lines and columns are counted from there.
+/// Created by `File::sub_file`.
+pub struct SubFile<'a, T: FileData> {
+    file: &'a File<T>,
+
+    /// The part of `file` that this views.
+    span: Span,
+
+    /// Local positions of line beginnings.
+    lines: Vec<Pos>,
+}
+
+impl<'a, T: FileData> SubFile<'a, T> {
+    /// Gets the file that this is part of.
+    pub fn file(&self) -> &'a File<T> {
+        self.file
+    }
+
+    /// Gets the span within the file that this views.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Gets the source text of this part of the file.
+    pub fn text(&self) -> &'a str {
+        self.file.source_slice(self.span)
+    }
+
+    /// Gets the line and column of a local position, counting from the start of this part.
+    ///
+    /// # Panics
+    ///
+    /// * If `pos` is beyond the end of this part
+    /// * If `pos` points to a byte in the middle of a UTF-8 character
+    pub fn find_line_col(&self, pos: Pos) -> LineCol {
+        assert!(pos.0 as u64 <= self.span.len());
+        let line = match self.lines.binary_search(&pos) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let column = self.text()[self.lines[line].0 as usize..pos.0 as usize]
+            .chars()
+            .count();
+        LineCol { line, column }
+    }
+
+    /// Translates a local position to the corresponding position in the `CodeMap`.
+    ///
+    /// # Panics
+    ///
+    /// * If `pos` is beyond the end of this part
+    pub fn to_parent(&self, pos: Pos) -> Pos {
+        assert!(pos.0 as u64 <= self.span.len());
+        self.span.low + pos.0 as u64
+    }
+}
+
+/// Resolves positions in a `File` to lines and columns, caching per-line character boundaries.
+///
+/// Created by `File::line_col_resolver`.
+pub struct LineColResolver<'a, T: FileData> {
+    file: &'a File<T>,
+
+    /// Byte offsets (relative to the line start) of each character of the lines seen so far.
+    char_starts: HashMap<usize, Vec<u32>>,
+}
+
+impl<'a, T: FileData> LineColResolver<'a, T> {
+    /// Gets the line and column of a Pos.
+    ///
+    /// Returns the same result as `File::find_line_col`.
+    ///
+    /// # Panics
+    ///
+    /// * If `pos` is not with this file's span
+    /// * If `pos` points to a byte in the middle of a UTF-8 character
+    pub fn find_line_col(&mut self, pos: Pos) -> LineCol {
+        let file = self.file;
+        let line = file.find_line(pos);
+        let line_span = file.line_span(line);
+        let starts = self.char_starts.entry(line).or_insert_with(|| {
+            file.source_slice(line_span)
+                .char_indices()
+                .map(|(i, _)| i as u32)
+                .collect()
+        });
+
+        let byte_col = (pos - line_span.low) as u32;
+        let column = match starts.binary_search(&byte_col) {
+            Ok(i) => i,
+            Err(i) if u64::from(byte_col) == line_span.len() => i,
+            Err(_) => panic!("byte index {} is not a char boundary", byte_col),
+        };
+
+        LineCol { line, column }
+    }
 }
 
 impl<T: FileData> fmt::Debug for File<T> {
@@ -134,6 +905,37 @@ impl<T: FileData> Hash for File<T> {
     }
 }
 
+/// Whether a line's span includes its line terminator.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum LineSpanKind {
+    /// The span includes the trailing `\n` or `\r\n`, as returned by `File::line_span`.
+    WithTerminator,
+
+    /// The span covers only the text of the line, as returned by `File::line_content_span`.
+    ContentOnly,
+}
+
+/// How `File::find_line_col_with_mode` counts columns.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum ColumnMode {
+    /// Each character is one column, as in `File::find_line_col`.
+    Chars,
+
+    /// Like `Chars`, except that zero width joiners (U+200D), the characters they join to the
+    /// preceding character, and variation selectors do not start a new column.
+    ///
+    /// This makes emoji ZWJ sequences such as 👨‍👩‍👧 and emoji presentation sequences such as ❤️
+    /// count as one column each. It is not full grapheme cluster segmentation: combining marks,
+    /// for example, still count as columns.
+    JoinedEmoji,
+}
+
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+
+fn is_variation_selector(c: char) -> bool {
+    matches!(c, '\u{FE00}'..='\u{FE0F}' | '\u{E0100}'..='\u{E01EF}')
+}
+
 /// A line and column.
 #[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
 pub struct LineCol {
@@ -180,6 +982,13 @@ impl<T: FileData> std::cmp::PartialEq for Loc<T> {
     }
 }
 
+impl<T: FileData> Hash for Loc<T> {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.file.hash(hasher);
+        self.position.hash(hasher);
+    }
+}
+
 /// A file, and a line and column range within it.
 #[derive(Debug, Eq)]
 pub struct SpanLoc<T: FileData> {
@@ -188,6 +997,32 @@ pub struct SpanLoc<T: FileData> {
     pub end: LineCol,
 }
 
+impl<T: FileData> SpanLoc<T> {
+    /// Converts to an `OwnedSpanLoc`, which holds the file name instead of the `File`.
+    pub fn to_owned_location(&self) -> OwnedSpanLoc {
+        OwnedSpanLoc {
+            file_name: self.file.name_string(),
+            begin: self.begin,
+            end: self.end,
+        }
+    }
+
+    /// Checks whether the span begins and ends on the same line.
+    pub fn is_single_line(&self) -> bool {
+        self.begin.line == self.end.line
+    }
+
+    /// Gets the number of columns between the beginning and end of the span, or `None` if the
+    /// span covers more than one line.
+    pub fn width(&self) -> Option<usize> {
+        if self.is_single_line() {
+            Some(self.end.column - self.begin.column)
+        } else {
+            None
+        }
+    }
+}
+
 impl<T: FileData> Clone for SpanLoc<T> {
     fn clone(&self) -> Self {
         Self {
@@ -203,6 +1038,15 @@ impl<T: FileData> std::cmp::PartialEq for SpanLoc<T> {
         self.begin == other.begin && self.end == other.end && self.file == other.file
     }
 }
+
+impl<T: FileData> Hash for SpanLoc<T> {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.file.hash(hasher);
+        self.begin.hash(hasher);
+        self.end.hash(hasher);
+    }
+}
+
 impl<T: FileData> fmt::Display for SpanLoc<T> {
     /// Formats the span as `filename:start_line:start_column: end_line:end_column`,
     /// or if the span is zero-length, `filename:line:column`, with a 1-indexed line and column.
@@ -229,9 +1073,42 @@ impl<T: FileData> fmt::Display for SpanLoc<T> {
     }
 }
 
+/// A file name, and a line and column range within it, detached from the `File`.
+#[derive(Clone, Hash, Eq, PartialEq, Debug)]
+pub struct OwnedSpanLoc {
+    pub file_name: String,
+    pub begin: LineCol,
+    pub end: LineCol,
+}
+
+impl fmt::Display for OwnedSpanLoc {
+    /// Formats the span in the same way as `SpanLoc`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        if self.begin == self.end {
+            write!(
+                f,
+                "{}:{}:{}",
+                self.file_name,
+                self.begin.line + 1,
+                self.begin.column + 1
+            )
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}:{}",
+                self.file_name,
+                self.begin.line + 1,
+                self.begin.column + 1,
+                self.end.line + 1,
+                self.end.column + 1
+            )
+        }
+    }
+}
+
 /// A wrapper around a `Box<str>` that meets the requirements for `FileData::Source` and `FileData::Name`.
 /// This type is used in `DefaultFileData` because   
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[repr(transparent)]
 pub struct BoxStr(Box<str>);
 impl BoxStr {
@@ -282,7 +1159,7 @@ impl PartialEq<str> for BoxStr {
 }
 
 /// A default implementation of `FileData` that contains
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DefaultFileData {
     name: BoxStr,
     contents: BoxStr,
@@ -295,6 +1172,18 @@ impl DefaultFileData {
             contents: BoxStr(contents.into_boxed_str()),
         }
     }
+
+    /// Creates file data like `new`, appending a `\n` to `contents` if it does not already end
+    /// with one.
+    ///
+    /// Because the empty line following a final newline is counted, a file created this way
+    /// always has `num_lines` one greater than the number of newline-terminated lines.
+    pub fn with_trailing_newline(name: String, mut contents: String) -> Self {
+        if !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        Self::new(name, contents)
+    }
 }
 
 impl FileData for DefaultFileData {
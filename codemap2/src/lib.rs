@@ -25,57 +25,567 @@ mod pos;
 pub use pos::*;
 mod file;
 pub use file::*;
+mod bytes;
+pub use bytes::*;
 
-use std::cmp::Ordering;
+/// Re-exports of the commonly used types and traits, for glob importing.
+///
+/// # Example
+/// ```
+/// use codemap2::prelude::*;
+///
+/// let mut codemap: CodeMap = CodeMap::new();
+/// let file = codemap.add_file(DefaultFileData::new("test.rs".to_owned(), "let x = 1;".to_owned()));
+/// let name = Spanned { node: "x", span: file.span.subspan(4, 5) };
+///
+/// let erased: &dyn ErasedCodeMap = &codemap;
+/// assert_eq!(erased.look_up_span(name.span).to_string(), "test.rs:1:5: 1:6");
+/// ```
+pub mod prelude {
+    pub use {
+        CodeMap, DefaultFileData, ErasedCodeMap, File, FileData, LineCol, Loc, Pos, Span, SpanLoc,
+        Spanned,
+    };
+}
+
+use std::cmp::{self, Ordering};
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
+use std::ops::{Range, RangeInclusive};
 
-use std::sync::Arc;
+use std::sync::atomic::{self, AtomicU32};
+use std::sync::{Arc, OnceLock};
 
 extern crate memchr;
-use memchr::memchr_iter;
+use memchr::{memchr, memchr_iter};
+
+extern crate unicode_width;
+
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// The reason a file could not be added with `CodeMap::add_file_at`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlacementError {
+    /// The file would overlap the existing file with the given span.
+    Overlaps(Span),
+    /// The file would extend past the end of the 4GiB position space.
+    OutOfRange,
+}
+
+impl fmt::Display for PlacementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            PlacementError::Overlaps(span) => {
+                write!(f, "file would overlap existing file at {:?}", span)
+            }
+            PlacementError::OutOfRange => write!(f, "file would exceed the 4GiB position space"),
+        }
+    }
+}
+
+impl std::error::Error for PlacementError {}
+
+/// An identifier distinguishing a `CodeMap` from the others created by the program.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub struct CodeMapId(u32);
+
+/// A `Pos` tagged with the id of the `CodeMap` it belongs to, created by `CodeMap::tag`.
+///
+/// `CodeMap::look_up_tagged` checks the tag in debug builds, catching positions that are
+/// accidentally used with the wrong `CodeMap`.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub struct TaggedPos {
+    pub map_id: CodeMapId,
+    pub pos: Pos,
+}
+
+/// A style of formatting a position, for `CodeMap::format_pos`.
+///
+/// Lines and columns are 1-indexed in all styles.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum PosFormat {
+    /// `file:line:column`, as used by GCC, Clang, and `Loc`'s `Display` implementation.
+    Colon,
+
+    /// `file(line,column)`, as used by MSVC.
+    Parens,
+
+    /// `file +line`, the syntax for opening a file at a line in editors such as Vim.
+    EditorLine,
+}
 
 /// A data structure recording source code files for position lookup.
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct CodeMap<T: FileData = DefaultFileData> {
+    id: CodeMapId,
     end_pos: Pos,
+    /// Sorted by position, which `try_find_file` relies on to binary search.
     files: Vec<Arc<File<T>>>,
 }
 
+impl<T: FileData> Default for CodeMap<T> {
+    fn default() -> Self {
+        CodeMap::new()
+    }
+}
+
 impl<T: FileData> CodeMap<T> {
     /// Creates an empty `CodeMap`.
     pub fn new() -> Self {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
         CodeMap {
+            id: CodeMapId(NEXT_ID.fetch_add(1, atomic::Ordering::Relaxed)),
             end_pos: Pos(0),
             files: vec![],
         }
     }
 
+    /// Gets the id of this `CodeMap`, which is different from that of every other `CodeMap`
+    /// created by the program.
+    pub fn id(&self) -> CodeMapId {
+        self.id
+    }
+
+    /// Tags a position with the id of this `CodeMap`.
+    pub fn tag(&self, pos: Pos) -> TaggedPos {
+        TaggedPos {
+            map_id: self.id,
+            pos,
+        }
+    }
+
     /// Adds a file with the given name and contents.
     ///
     /// Use the returned `File` and its `.span` property to create `Spans`
     /// representing substrings of the file.
     pub fn add_file(&mut self, source: T) -> Arc<File<T>> {
         let low = self.end_pos + 1;
+        debug_assert!(
+            u64::from(low.0) + source.source().as_ref().len() as u64 <= u64::from(u32::MAX),
+            "CodeMap exceeded the 4GiB position space"
+        );
+        let file = Arc::new(Self::new_file(source, low, FileId(self.files.len() as u32)));
+        self.insert_file(file.clone());
+        file
+    }
+
+    /// Adds a file like `add_file`, but with the given line table instead of one computed by
+    /// finding the newlines in the source.
+    ///
+    /// This is useful when line structure comes from elsewhere, such as the line table of a
+    /// compiled artifact. `line_starts` holds the position of the start of each line, as returned
+    /// by `File::line_starts`. The file will start at `self.total_span().high() + 1`.
+    ///
+    /// The line table is replaced by one computed from the newlines if the file is later
+    /// modified with `with_file_mut`.
+    ///
+    /// # Panics
+    ///
+    ///   * If `line_starts` does not begin with the start of the file
+    ///   * If `line_starts` is not strictly increasing
+    ///   * If any line start is past the end of the file or in the middle of a UTF-8 character
+    pub fn add_file_with_lines(&mut self, source: T, line_starts: Vec<Pos>) -> Arc<File<T>> {
+        let low = self.end_pos + 1;
+        debug_assert!(
+            u64::from(low.0) + source.source().as_ref().len() as u64 <= u64::from(u32::MAX),
+            "CodeMap exceeded the 4GiB position space"
+        );
+        let mut file = Self::new_file(source, low, FileId(self.files.len() as u32));
+        let src = file.source.source().as_ref();
+        assert_eq!(
+            line_starts.first(),
+            Some(&low),
+            "line table must begin with the start of the file"
+        );
+        assert!(
+            line_starts.windows(2).all(|w| w[0] < w[1]),
+            "line table is not strictly increasing"
+        );
+        assert!(
+            line_starts
+                .iter()
+                .all(|&pos| pos <= file.span.high && src.is_char_boundary((pos - low) as usize)),
+            "line table contains a position outside of the file or inside a character"
+        );
+        file.lines = line_starts;
+
+        let file = Arc::new(file);
+        self.insert_file(file.clone());
+        file
+    }
+
+    /// Adds a file with the given name and contents, starting at the position `low`.
+    ///
+    /// This allows position ranges to be reserved, for example to interleave the `CodeMap` with
+    /// an external position scheme. Files added later with `add_file` are still placed after all
+    /// existing files.
+    ///
+    /// Returns an error, and does not add the file, if it would overlap an existing file or
+    /// extend past the end of the position space.
+    pub fn add_file_at(&mut self, source: T, low: Pos) -> Result<Arc<File<T>>, PlacementError> {
+        let len = source.source().as_ref().len() as u64;
+        if u64::from(low.0) + len > u64::from(u32::MAX) {
+            return Err(PlacementError::OutOfRange);
+        }
+        let high = low + len;
+
+        let index = self.files.partition_point(|file| file.span.low < low);
+        if let Some(prev) = index.checked_sub(1).map(|i| &self.files[i]) {
+            if prev.span.high >= low {
+                return Err(PlacementError::Overlaps(prev.span));
+            }
+        }
+        if let Some(next) = self.files.get(index) {
+            if next.span.low <= high {
+                return Err(PlacementError::Overlaps(next.span));
+            }
+        }
+
+        let file = Arc::new(Self::new_file(source, low, FileId(self.files.len() as u32)));
+        self.insert_file(file.clone());
+        Ok(file)
+    }
+
+    /// Modifies the data of the file with the given id, then updates the file's span and line
+    /// index to match its new contents.
+    ///
+    /// If the `CodeMap` holds the only reference to the file, its data is modified in place.
+    /// Otherwise the data is cloned and the `CodeMap` is given a new `File`; other references
+    /// keep seeing the old `File`, with its old contents and span.
+    ///
+    /// The file keeps its id and its starting position. Files added with `add_file` are placed
+    /// directly after one another, so only the last file can grow unless a gap was left with
+    /// `add_file_at`. If another file follows this one, the data is also cloned before being
+    /// modified in place, so that it can be restored if the new contents don't fit.
+    ///
+    /// The line index is always recomputed from the newlines in the new contents, so a line
+    /// table given to `add_file_with_lines` is discarded.
+    ///
+    /// # Panics
+    ///
+    ///   * If there is no file with the given id
+    ///   * If the new contents would overlap the next file. The `CodeMap` is left unchanged.
+    pub fn with_file_mut<F: FnOnce(&mut T)>(&mut self, id: FileId, f: F) -> Arc<File<T>>
+    where
+        T: Clone,
+    {
+        let index = self
+            .files
+            .iter()
+            .position(|file| file.id == id)
+            .unwrap_or_else(|| panic!("no file with {:?}", id));
+        let (files, rest) = self.files.split_at_mut(index + 1);
+        let (arc, next) = (&mut files[index], rest.first());
+        let low = arc.span.low;
+        let fits = |source: &T| {
+            next.is_none_or(|next| next.span.low > low + source.source().as_ref().len() as u64)
+        };
+
+        match Arc::get_mut(arc) {
+            Some(file) => {
+                let backup = next.map(|_| file.source.clone());
+                f(&mut file.source);
+                if !fits(&file.source) {
+                    file.source = backup.unwrap();
+                    panic!(
+                        "file {} no longer fits before file {}",
+                        file.name(),
+                        next.unwrap().name()
+                    );
+                }
+                let src = file.source.source().as_ref();
+                file.span = Span {
+                    low,
+                    high: low + src.len() as u64,
+                };
+                file.lines = Self::index_lines(src, low);
+                file.max_line_widths = OnceLock::new();
+                file.total_chars = OnceLock::new();
+            }
+            None => {
+                let mut source = arc.source.clone();
+                f(&mut source);
+                assert!(
+                    fits(&source),
+                    "file {} no longer fits before file {}",
+                    arc.name(),
+                    next.unwrap().name()
+                );
+                *arc = Arc::new(Self::new_file(source, low, id));
+            }
+        }
+
+        self.end_pos = cmp::max(self.end_pos, arc.span.high);
+        arc.clone()
+    }
+
+    /// Moves all files so that they are placed directly after one another starting from
+    /// `Pos(1)`, as if they had been added with `add_file`, removing any gaps left by
+    /// `add_file_at`.
+    ///
+    /// Returns a function mapping a position from before the move to the same position
+    /// afterwards, or to `None` if it was not within any file. Spans and `File`s obtained before
+    /// compacting keep their old positions, so they must be translated with this function.
+    ///
+    /// A file's data is cloned only if the file needs to move and the `CodeMap` does not hold the
+    /// only reference to it.
+    pub fn compact(&mut self) -> impl Fn(Pos) -> Option<Pos>
+    where
+        T: Clone,
+    {
+        let mut moves = Vec::with_capacity(self.files.len());
+        let mut low = Pos(1);
+        for arc in &mut self.files {
+            let old = arc.span;
+            moves.push((old, low));
+            if old.low != low {
+                let shift = |pos: Pos| low + (pos - old.low);
+                let span = Span {
+                    low,
+                    high: shift(old.high),
+                };
+                match Arc::get_mut(arc) {
+                    Some(file) => {
+                        file.span = span;
+                        for line in &mut file.lines {
+                            *line = shift(*line);
+                        }
+                    }
+                    None => {
+                        *arc = Arc::new(File {
+                            span,
+                            id: arc.id,
+                            source: arc.source.clone(),
+                            lines: arc.lines.iter().map(|&line| shift(line)).collect(),
+                            max_line_widths: OnceLock::new(),
+                            total_chars: OnceLock::new(),
+                        });
+                    }
+                }
+            }
+            low = arc.span.high + 1;
+        }
+        self.end_pos = self.files.last().map_or(Pos(0), |file| file.span.high);
+
+        move |pos| {
+            let i = moves.partition_point(|&(old, _)| old.high < pos);
+            match moves.get(i) {
+                Some(&(old, new_low)) if old.low <= pos => Some(new_low + (pos - old.low)),
+                _ => None,
+            }
+        }
+    }
+
+    /// Checks the consistency of the `CodeMap`'s internal state, returning a description of the
+    /// first problem found.
+    ///
+    /// This checks that files are sorted by position and separated by at least one position,
+    /// that the end position is not before the end of the last file, and that each file's line
+    /// starts are strictly increasing, begin at the start of the file, and lie within it. Gaps
+    /// larger than one position are allowed, as `add_file_at` can leave them, and the end
+    /// position may be past the last file if `with_file_mut` has shrunk it. It is meant for
+    /// assertions while fuzzing or testing code that modifies the `CodeMap`.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        for pair in self.files.windows(2) {
+            if pair[0].span.high >= pair[1].span.low {
+                return Err(format!(
+                    "file {} at {:?} is not before file {} at {:?}",
+                    pair[0].name(),
+                    pair[0].span,
+                    pair[1].name(),
+                    pair[1].span
+                ));
+            }
+        }
+        if let Some(last) = self.files.last() {
+            if self.end_pos < last.span.high {
+                return Err(format!(
+                    "end position {:?} is before the end of file {} at {:?}",
+                    self.end_pos,
+                    last.name(),
+                    last.span
+                ));
+            }
+        }
+        for file in &self.files {
+            if file.lines.first() != Some(&file.span.low) {
+                return Err(format!(
+                    "line table of file {} does not begin at {:?}",
+                    file.name(),
+                    file.span.low
+                ));
+            }
+            if let Some(w) = file.lines.windows(2).find(|w| w[0] >= w[1]) {
+                return Err(format!(
+                    "line table of file {} is not strictly increasing at {:?}",
+                    file.name(),
+                    w[1]
+                ));
+            }
+            if let Some(line) = file.lines.last().filter(|&&line| line > file.span.high) {
+                return Err(format!(
+                    "line start {:?} is past the end of file {} at {:?}",
+                    line,
+                    file.name(),
+                    file.span
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts a file that does not overlap any existing file, keeping `files` sorted.
+    fn insert_file(&mut self, file: Arc<File<T>>) {
+        let index = self.files.partition_point(|f| f.span.low < file.span.low);
+        self.end_pos = cmp::max(self.end_pos, file.span.high);
+        self.files.insert(index, file);
+    }
+
+    fn new_file(source: T, low: Pos, id: FileId) -> File<T> {
         let src = source.source().as_ref();
+        debug_assert!(
+            std::str::from_utf8(src.as_bytes()).is_ok(),
+            "FileData::source is not valid UTF-8"
+        );
         let high = low + src.len() as u64;
-        self.end_pos = high;
+        let lines = Self::index_lines(src, low);
+
+        File {
+            span: Span { low, high },
+            id,
+            source,
+            lines,
+            max_line_widths: OnceLock::new(),
+            total_chars: OnceLock::new(),
+        }
+    }
+
+    fn index_lines(src: &str, low: Pos) -> Vec<Pos> {
         let mut lines = vec![low];
 
         let iter = memchr_iter(b'\n', src.as_bytes()).map(|i| low + (i + 1) as u64);
         lines.extend(iter);
+        lines
+    }
 
-        let file = Arc::new(File {
-            span: Span { low, high },
-            source,
-            lines,
-        });
+    /// Adds each of the given files like `add_file`, in order.
+    ///
+    /// Returns the added files keyed by their names, formatted with `Display`. If several files
+    /// have the same name, the map holds the last of them, but all are added.
+    pub fn add_files<I>(&mut self, sources: I) -> HashMap<String, Arc<File<T>>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        sources
+            .into_iter()
+            .map(|source| {
+                let file = self.add_file(source);
+                (file.name_string(), file)
+            })
+            .collect()
+    }
 
-        self.files.push(file.clone());
-        file
+    /// Adds a file like `add_file`, after checking that its source is valid UTF-8.
+    ///
+    /// The `str` returned by `FileData::source` is always valid UTF-8 unless a `FileData`
+    /// implementation has used `unsafe` code incorrectly. Use this instead of `add_file` to check
+    /// that guarantee when registering files from untrusted `FileData` implementations; the file
+    /// is not added if the check fails. `add_file` only performs the check in debug builds.
+    pub fn add_file_validated(&mut self, source: T) -> Result<Arc<File<T>>, std::str::Utf8Error> {
+        std::str::from_utf8(source.source().as_ref().as_bytes())?;
+        Ok(self.add_file(source))
+    }
+
+    /// Gets the span covering all files added so far, including the gaps between them.
+    ///
+    /// Returns an empty span if no files have been added.
+    pub fn total_span(&self) -> Span {
+        match self.files.first() {
+            Some(first) => Span {
+                low: first.span.low,
+                high: self.end_pos,
+            },
+            None => Span {
+                low: self.end_pos,
+                high: self.end_pos,
+            },
+        }
+    }
+
+    /// Gets the total length in bytes of all files added so far.
+    pub fn total_bytes(&self) -> usize {
+        self.files
+            .iter()
+            .map(|file| file.source().as_ref().len())
+            .sum()
+    }
+
+    /// Gets the total number of characters (not bytes) in all files added so far.
+    ///
+    /// Each file's character count is cached, so this is cheap after the first call.
+    pub fn total_chars(&self) -> usize {
+        self.files.iter().map(|file| file.total_chars()).sum()
+    }
+
+    /// Gets all files added so far, sorted by position.
+    pub fn files(&self) -> &[Arc<File<T>>] {
+        &self.files
+    }
+
+    /// Gets the range of indices into `files()` of the files that a span overlaps.
+    ///
+    /// The span is treated as the closed range `low..=high`, matching `find_file`, so a span
+    /// ending at the end of a file does not overlap the file after it.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` does not overlap any file
+    pub fn file_index_range(&self, span: Span) -> RangeInclusive<usize> {
+        let first = self.files.partition_point(|file| file.span.high < span.low);
+        let end = self
+            .files
+            .partition_point(|file| file.span.low <= span.high);
+        assert!(
+            first < end,
+            "{:?} does not overlap any file (files cover {:?})",
+            span,
+            self.total_span()
+        );
+        first..=end - 1
+    }
+
+    /// Looks up a `File` by its id.
+    pub fn file_by_id(&self, id: FileId) -> Option<&Arc<File<T>>> {
+        self.files.iter().find(|file| file.id == id)
     }
 
     /// Looks up the `File` that contains the specified position.
+    ///
+    /// # Panics
+    ///
+    ///   * If `pos` is not within any file
     pub fn find_file(&self, pos: Pos) -> &Arc<File<T>> {
+        self.try_find_file(pos).unwrap_or_else(|| {
+            assert!(
+                !self.files.is_empty(),
+                "Mapping source location {:?}, but the CodeMap has no files",
+                pos
+            );
+            panic!(
+                "Mapping unknown source location {:?} (files cover {:?})",
+                pos,
+                self.total_span()
+            )
+        })
+    }
+
+    /// Looks up the `File` that contains the specified position, or returns `None` if `pos` is
+    /// not within any file.
+    pub fn try_find_file(&self, pos: Pos) -> Option<&Arc<File<T>>> {
         self.files
             .binary_search_by(|file| {
                 if file.span.high < pos {
@@ -88,7 +598,146 @@ impl<T: FileData> CodeMap<T> {
             })
             .ok()
             .map(|i| &self.files[i])
-            .expect("Mapping unknown source location")
+    }
+
+    /// Splits `spans` into those that are entirely within a single file, and those that are not
+    /// and so cannot be passed to `look_up_span`.
+    ///
+    /// The order of the spans is kept within each of the two lists.
+    pub fn partition_valid_spans(&self, spans: &[Span]) -> (Vec<Span>, Vec<Span>) {
+        spans.iter().partition(|span| {
+            self.try_find_file(span.low)
+                .is_some_and(|file| span.high <= file.span.high)
+        })
+    }
+
+    /// Gets the name of the file containing a `Pos` without cloning the file's `Arc`, or `None`
+    /// if `pos` is not within any file.
+    ///
+    /// For name types that can't be borrowed as a `str`, `ErasedCodeMap::file_name` returns the
+    /// name as a `String` instead.
+    pub fn file_name_of(&self, pos: Pos) -> Option<&str>
+    where
+        T::Name: AsRef<str>,
+    {
+        self.try_find_file(pos).map(|file| file.name().as_ref())
+    }
+
+    /// Shifts `pos` by `delta` bytes, returning `None` if the result would leave the file
+    /// containing `pos` (or if `pos` is not within any file).
+    ///
+    /// The end of a file (`file.span.high()`) is considered to be within it.
+    pub fn offset_within_file(&self, pos: Pos, delta: i64) -> Option<Pos> {
+        let file = self.try_find_file(pos)?;
+        let shifted = i64::from(pos.0) + delta;
+        if shifted < i64::from(file.span.low.0) || shifted > i64::from(file.span.high.0) {
+            return None;
+        }
+        Some(Pos(shifted as u32))
+    }
+
+    /// Extends a span to the end of the line following its last line.
+    ///
+    /// If the span already ends on the last line of its file, it is extended to the end of the
+    /// file instead.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within one file
+    pub fn extend_span_to_next_line(&self, span: Span) -> Span {
+        let file = self.find_file(span.low);
+        let next = file.lines_touched(span).end() + 1;
+        if next < file.num_lines() {
+            span.with_high(file.line_span(next).high)
+        } else {
+            span.with_high(file.span.high)
+        }
+    }
+
+    /// Extends a span to the beginning of the line preceding its first line.
+    ///
+    /// If the span already begins on the first line of its file, it is extended to the beginning
+    /// of the file instead.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within one file
+    pub fn extend_span_to_prev_line(&self, span: Span) -> Span {
+        let file = self.find_file(span.low);
+        match file.find_line(span.low) {
+            0 => span.with_low(file.span.low),
+            first => span.with_low(file.line_span(first - 1).low),
+        }
+    }
+
+    /// Gets the full source text of each line that a span touches, in order.
+    ///
+    /// Each line is returned whole, including the parts outside the span and its line
+    /// terminator, so concatenating them gives the source of every touched line.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within one file
+    pub fn span_lines_text(&self, span: Span) -> Vec<&str> {
+        let file = self.find_file(span.low);
+        file.lines_touched(span)
+            .map(|line| file.source_slice(file.line_span(line)))
+            .collect()
+    }
+
+    /// Splits a span after the first `\n` within it.
+    ///
+    /// Returns the part of the span up to and including the first `\n`, and the rest of the span.
+    /// If the span contains no `\n`, or its only `\n` is its last byte, the whole span is
+    /// returned with `None`.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within one file
+    pub fn split_span_at_first_newline(&self, span: Span) -> (Span, Option<Span>) {
+        let file = self.find_span_file(span);
+        match memchr(b'\n', file.source_slice(span).as_bytes()) {
+            Some(i) if (i as u64) + 1 < span.len() => {
+                let split = span.low + (i as u64 + 1);
+                (span.with_high(split), Some(span.with_low(split)))
+            }
+            _ => (span, None),
+        }
+    }
+
+    /// Gets the number of characters (not bytes) in the source text of a span.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within one file
+    pub fn char_len_of_span(&self, span: Span) -> usize {
+        self.find_span_file(span).char_count(span)
+    }
+
+    /// Checks whether a span begins and ends on the same line.
+    ///
+    /// A span ending just after a line terminator ends on the following line.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within one file
+    pub fn is_single_line(&self, span: Span) -> bool {
+        let file = self.find_file(span.low);
+        file.find_line(span.low) == file.find_line(span.high)
+    }
+
+    /// Gets the span covering the whole lines of two positions in the same file, from the
+    /// beginning of the earlier line to the end of the later one, including its terminator.
+    ///
+    /// The positions may be given in either order. Returns `None` if they are in different files,
+    /// or if either is not within any file.
+    pub fn enclosing_lines(&self, a: Pos, b: Pos) -> Option<Span> {
+        let file = self.try_find_file(a)?;
+        if !file.span.contains_pos_inclusive(b) {
+            return None;
+        }
+        let (first, last) = (file.find_line(a.min(b)), file.find_line(a.max(b)));
+        Some(file.line_span(first).merge(file.line_span(last)))
     }
 
     /// Gets the file, line, and column represented by a `Pos`.
@@ -101,9 +750,99 @@ impl<T: FileData> CodeMap<T> {
         }
     }
 
+    /// Gets the id of the file containing a `Pos`, and the line and column within that file.
+    ///
+    /// Unlike `look_up_pos`, this does not clone the file's `Arc`. Use `file_by_id` to get the
+    /// file back from its id.
+    ///
+    /// # Panics
+    ///
+    ///   * If `pos` is not within any file
+    pub fn resolve_light(&self, pos: Pos) -> (FileId, LineCol) {
+        let file = self.find_file(pos);
+        (file.id, file.find_line_col(pos))
+    }
+
+    /// Gets the file, line, and column represented by a `TaggedPos`.
+    ///
+    /// # Panics
+    ///
+    ///   * In debug builds, if `pos` was tagged by a different `CodeMap`
+    ///   * If `pos` is not within any file
+    pub fn look_up_tagged(&self, pos: TaggedPos) -> Loc<T> {
+        debug_assert_eq!(
+            pos.map_id, self.id,
+            "position {:?} belongs to a different CodeMap",
+            pos.pos
+        );
+        self.look_up_pos(pos.pos)
+    }
+
+    /// Gets the file containing a `Pos`, and the line number and text of the line it is on,
+    /// for showing context around an insertion point.
+    ///
+    /// The text does not include the line terminator. A position at the start of a line is on
+    /// that line, not the one before it.
+    ///
+    /// # Panics
+    ///
+    ///   * If `pos` is not within any file
+    pub fn context_line_for_pos(&self, pos: Pos) -> (Arc<File<T>>, usize, String) {
+        let file = self.find_file(pos);
+        let line = file.find_line(pos);
+        (file.clone(), line, file.source_line(line).to_owned())
+    }
+
+    /// Gets the line numbers and text of the lines within `radius` lines of the line containing
+    /// `pos`, for showing a preview around it.
+    ///
+    /// At most `2 * radius + 1` lines are returned, in order, with fewer where the range is
+    /// clamped at the start or end of the file. The text does not include line terminators.
+    ///
+    /// # Panics
+    ///
+    ///   * If `pos` is not within any file
+    pub fn lines_around(&self, pos: Pos, radius: usize) -> Vec<(usize, &str)> {
+        let file = self.find_file(pos);
+        let line = file.find_line(pos);
+        let last = cmp::min(line.saturating_add(radius), file.num_lines() - 1);
+        (line.saturating_sub(radius)..=last)
+            .map(|i| (i, file.source_line(i)))
+            .collect()
+    }
+
+    /// Gets the file, line, and column represented by a `Pos`, along with the offset in bytes of
+    /// the `Pos` from the beginning of its line.
+    pub fn look_up_pos_bytes(&self, pos: Pos) -> (Loc<T>, usize) {
+        let loc = self.look_up_pos(pos);
+        let byte_col = pos - loc.file.line_span(loc.position.line).low;
+        (loc, byte_col as usize)
+    }
+
+    /// Gets the file, line, and column of each `Pos`, resolving them in parallel.
+    ///
+    /// The result is the same as calling `look_up_pos` on each position in order. Spreading the
+    /// work over threads only pays off for large batches, such as every token in a big file.
+    ///
+    /// Requires the `rayon` feature.
+    #[cfg(feature = "rayon")]
+    pub fn resolve_par(&self, positions: &[Pos]) -> Vec<Loc<T>>
+    where
+        T: Send + Sync,
+    {
+        positions
+            .par_iter()
+            .map(|&pos| self.look_up_pos(pos))
+            .collect()
+    }
+
     /// Gets the file and its line and column ranges represented by a `Span`.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within one file
     pub fn look_up_span(&self, span: Span) -> SpanLoc<T> {
-        let file = self.find_file(span.low);
+        let file = self.find_span_file(span);
         let begin = file.find_line_col(span.low);
         let end = file.find_line_col(span.high);
         SpanLoc {
@@ -112,47 +851,284 @@ impl<T: FileData> CodeMap<T> {
             end,
         }
     }
-}
 
-#[test]
-fn test_codemap() {
-    let mut codemap = CodeMap::new();
-    let f1 = codemap.add_file(DefaultFileData::new(
-        "test1.rs".to_string(),
-        "abcd\nefghij\nqwerty".to_string(),
-    ));
-    let f2 = codemap.add_file(DefaultFileData::new(
-        "test2.rs".to_string(),
-        "foo\nbar".to_string(),
-    ));
-
-    assert_eq!(codemap.find_file(f1.span.low()).name(), "test1.rs");
-    assert_eq!(codemap.find_file(f1.span.high()).name(), "test1.rs");
-    assert_eq!(codemap.find_file(f2.span.low()).name(), "test2.rs");
-    assert_eq!(codemap.find_file(f2.span.high()).name(), "test2.rs");
+    /// Gets the file and line and column ranges of each part of a span that may cross files.
+    ///
+    /// Returns one `SpanLoc` per file that the span overlaps, in order, each covering only the
+    /// part of the span within that file. A span within a single file gives the same result as
+    /// `look_up_span`. Gaps between files are skipped.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` does not overlap any file
+    pub fn look_up_span_multi(&self, span: Span) -> Vec<SpanLoc<T>> {
+        self.files[self.file_index_range(span)]
+            .iter()
+            .map(|file| {
+                let low = cmp::max(span.low, file.span.low);
+                let high = cmp::min(span.high, file.span.high);
+                SpanLoc {
+                    file: file.clone(),
+                    begin: file.find_line_col(low),
+                    end: file.find_line_col(high),
+                }
+            })
+            .collect()
+    }
 
-    let x = f1.span.subspan(5, 10);
-    let f = codemap.find_file(x.low);
-    assert_eq!(f.name(), "test1.rs");
-    assert_eq!(
-        f.find_line_col(f.span.low()),
-        LineCol { line: 0, column: 0 }
-    );
-    assert_eq!(
-        f.find_line_col(f.span.low() + 4),
-        LineCol { line: 0, column: 4 }
-    );
-    assert_eq!(
-        f.find_line_col(f.span.low() + 5),
-        LineCol { line: 1, column: 0 }
-    );
-    assert_eq!(
-        f.find_line_col(f.span.low() + 16),
-        LineCol { line: 2, column: 4 }
-    );
+    /// Formats the location of a `Pos` in the given style.
+    ///
+    /// # Panics
+    ///
+    ///   * If `pos` is not within any file
+    pub fn format_pos(&self, pos: Pos, format: PosFormat) -> String {
+        let file = self.find_file(pos);
+        let LineCol { line, column } = file.find_line_col(pos);
+        match format {
+            PosFormat::Colon => format!("{}:{}:{}", file.name(), line + 1, column + 1),
+            PosFormat::Parens => format!("{}({},{})", file.name(), line + 1, column + 1),
+            PosFormat::EditorLine => format!("{} +{}", file.name(), line + 1),
+        }
+    }
 
-    let x = f2.span.subspan(4, 7);
-    assert_eq!(codemap.find_file(x.low()).name(), "test2.rs");
+    /// Writes the location of a `Pos` to `f` in the format of `Loc`'s `Display` implementation.
+    ///
+    /// This avoids constructing a `Loc`, which clones the file's `Arc`, so it is cheaper when
+    /// the location is formatted and then discarded.
+    ///
+    /// # Panics
+    ///
+    ///   * If `pos` is not within any file
+    pub fn fmt_pos(&self, pos: Pos, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let file = self.find_file(pos);
+        let position = file.find_line_col(pos);
+        write!(
+            f,
+            "{}:{}:{}",
+            file.name(),
+            position.line + 1,
+            position.column + 1
+        )
+    }
+
+    /// Writes the location of a `Span` to `f` in the format of `SpanLoc`'s `Display`
+    /// implementation, without constructing a `SpanLoc`.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within one file
+    pub fn fmt_span(&self, span: Span, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let file = self.find_span_file(span);
+        let begin = file.find_line_col(span.low);
+        let end = file.find_line_col(span.high);
+        if begin == end {
+            write!(f, "{}:{}:{}", file.name(), begin.line + 1, begin.column + 1)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}:{}",
+                file.name(),
+                begin.line + 1,
+                begin.column + 1,
+                end.line + 1,
+                end.column + 1
+            )
+        }
+    }
+
+    fn find_span_file(&self, span: Span) -> &Arc<File<T>> {
+        let file = self.find_file(span.low);
+        if span.high > file.span.high {
+            match self.try_find_file(span.high) {
+                Some(end_file) => panic!(
+                    "{:?} begins in file {} but ends in file {}",
+                    span,
+                    file.name(),
+                    end_file.name()
+                ),
+                None => panic!(
+                    "{:?} begins in file {} but ends outside of any file",
+                    span,
+                    file.name()
+                ),
+            }
+        }
+        file
+    }
+
+    /// Gets the file and its line and column ranges of a `Spanned` node.
+    pub fn resolve_spanned<U>(&self, spanned: &Spanned<U>) -> SpanLoc<T> {
+        self.look_up_span(spanned.span)
+    }
+
+    /// Gets the file and its line and column ranges represented by a range of raw byte offsets
+    /// into the `CodeMap`, such as one obtained by converting a `Span` into a `Range<usize>`.
+    ///
+    /// # Panics
+    ///
+    ///   * If `range.end < range.start`
+    ///   * If either end of the range does not fit in a `Pos`
+    pub fn look_up_byte_range(&self, range: Range<usize>) -> SpanLoc<T> {
+        assert!(range.end >= range.start);
+        let to_pos =
+            |offset| Pos(u32::try_from(offset).expect("byte offset does not fit in a `Pos`"));
+        self.look_up_span(Span {
+            low: to_pos(range.start),
+            high: to_pos(range.end),
+        })
+    }
+
+    /// Builds a mapping from positions in `old` to positions in `new`, for migrating spans after
+    /// a file is re-registered with changed contents.
+    ///
+    /// The mapping is based on the longest common prefix and suffix of the two sources:
+    /// positions in the common prefix map to the same offset in `new`, positions in the common
+    /// suffix are shifted by the difference in length, and positions in the changed region
+    /// between them map to `None`. The boundaries of the changed region are mapped. Positions
+    /// outside of `old` also map to `None`.
+    pub fn remap_file(&self, old: &File<T>, new: &File<T>) -> impl Fn(Pos) -> Option<Pos> {
+        let old_src = old.source().as_ref();
+        let new_src = new.source().as_ref();
+        let max = cmp::min(old_src.len(), new_src.len());
+
+        let mut prefix = old_src
+            .bytes()
+            .zip(new_src.bytes())
+            .take_while(|&(a, b)| a == b)
+            .count();
+        while !old_src.is_char_boundary(prefix) {
+            prefix -= 1;
+        }
+
+        let mut suffix = old_src
+            .bytes()
+            .rev()
+            .zip(new_src.bytes().rev())
+            .take(max - prefix)
+            .take_while(|&(a, b)| a == b)
+            .count();
+        while !old_src.is_char_boundary(old_src.len() - suffix) {
+            suffix -= 1;
+        }
+
+        let old_span = old.span;
+        let new_low = new.span.low;
+        let old_len = old_src.len() as u64;
+        let new_len = new_src.len() as u64;
+        let (prefix, suffix) = (prefix as u64, suffix as u64);
+        move |pos| {
+            if pos < old_span.low || pos > old_span.high {
+                return None;
+            }
+            let offset = pos - old_span.low;
+            if offset <= prefix {
+                Some(new_low + offset)
+            } else if offset >= old_len - suffix {
+                Some(new_low + (offset + new_len - old_len))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+impl CodeMap<DefaultFileData> {
+    /// Creates a `CodeMap` containing a file for each `(name, contents)` pair, in order.
+    ///
+    /// Returns the `CodeMap` along with the added files.
+    pub fn from_sources<I>(sources: I) -> (Self, Vec<Arc<File<DefaultFileData>>>)
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut codemap = CodeMap::new();
+        let files = sources
+            .into_iter()
+            .map(|(name, source)| codemap.add_file(DefaultFileData::new(name, source)))
+            .collect();
+        (codemap, files)
+    }
+}
+
+/// An object-safe view of a `CodeMap` that hides its `FileData` type.
+///
+/// This allows code such as a diagnostics layer to accept codemaps of any file type as
+/// `&dyn ErasedCodeMap`. It is implemented for every `CodeMap`.
+pub trait ErasedCodeMap {
+    /// Gets the file name and line and column ranges represented by a `Span`.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within one file
+    fn look_up_span(&self, span: Span) -> OwnedSpanLoc;
+
+    /// Gets the source text of a `Span`.
+    ///
+    /// # Panics
+    ///
+    ///   * If `span` is not entirely within one file
+    fn source_for_span(&self, span: Span) -> &str;
+
+    /// Gets the name of the file that contains the specified position.
+    ///
+    /// # Panics
+    ///
+    ///   * If `pos` is not within any file
+    fn file_name(&self, pos: Pos) -> String;
+}
+
+impl<T: FileData> ErasedCodeMap for CodeMap<T> {
+    fn look_up_span(&self, span: Span) -> OwnedSpanLoc {
+        CodeMap::look_up_span(self, span).to_owned_location()
+    }
+
+    fn source_for_span(&self, span: Span) -> &str {
+        self.find_span_file(span).source_slice(span)
+    }
+
+    fn file_name(&self, pos: Pos) -> String {
+        self.find_file(pos).name_string()
+    }
+}
+
+#[test]
+fn test_codemap() {
+    let mut codemap = CodeMap::new();
+    let f1 = codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_string(),
+        "abcd\nefghij\nqwerty".to_string(),
+    ));
+    let f2 = codemap.add_file(DefaultFileData::new(
+        "test2.rs".to_string(),
+        "foo\nbar".to_string(),
+    ));
+
+    assert_eq!(codemap.find_file(f1.span.low()).name(), "test1.rs");
+    assert_eq!(codemap.find_file(f1.span.high()).name(), "test1.rs");
+    assert_eq!(codemap.find_file(f2.span.low()).name(), "test2.rs");
+    assert_eq!(codemap.find_file(f2.span.high()).name(), "test2.rs");
+
+    let x = f1.span.subspan(5, 10);
+    let f = codemap.find_file(x.low);
+    assert_eq!(f.name(), "test1.rs");
+    assert_eq!(
+        f.find_line_col(f.span.low()),
+        LineCol { line: 0, column: 0 }
+    );
+    assert_eq!(
+        f.find_line_col(f.span.low() + 4),
+        LineCol { line: 0, column: 4 }
+    );
+    assert_eq!(
+        f.find_line_col(f.span.low() + 5),
+        LineCol { line: 1, column: 0 }
+    );
+    assert_eq!(
+        f.find_line_col(f.span.low() + 16),
+        LineCol { line: 2, column: 4 }
+    );
+
+    let x = f2.span.subspan(4, 7);
+    assert_eq!(codemap.find_file(x.low()).name(), "test2.rs");
     assert_eq!(codemap.find_file(x.high()).name(), "test2.rs");
 }
 
@@ -217,3 +1193,1748 @@ fn test_multibyte() {
         }
     );
 }
+
+#[test]
+fn test_loc_hash() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn hash_of<H: Hash>(value: &H) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "abc\ndef".to_owned(),
+    ));
+
+    let span = file.span.subspan(1, 5);
+    assert_eq!(codemap.look_up_span(span), codemap.look_up_span(span));
+    assert_eq!(
+        hash_of(&codemap.look_up_span(span)),
+        hash_of(&codemap.look_up_span(span))
+    );
+    assert_ne!(
+        hash_of(&codemap.look_up_span(span)),
+        hash_of(&codemap.look_up_span(file.span.subspan(1, 6)))
+    );
+
+    let pos = file.span.low() + 5;
+    assert_eq!(
+        hash_of(&codemap.look_up_pos(pos)),
+        hash_of(&codemap.look_up_pos(pos))
+    );
+    assert_ne!(
+        hash_of(&codemap.look_up_pos(pos)),
+        hash_of(&codemap.look_up_pos(pos + 1))
+    );
+}
+
+#[test]
+fn test_line_col_resolver() {
+    let mut codemap = CodeMap::new();
+    let content = "65°00′N 18°00′W 汉语\n🔬\nabc";
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        content.to_owned(),
+    ));
+
+    let mut resolver = file.line_col_resolver();
+    for (i, _) in content.char_indices().chain(Some((content.len(), ' '))) {
+        let pos = file.span.low() + i as u64;
+        assert_eq!(resolver.find_line_col(pos), file.find_line_col(pos));
+    }
+    // resolve again, this time from the cache
+    assert_eq!(
+        resolver.find_line_col(file.span.low() + 21),
+        LineCol {
+            line: 0,
+            column: 15
+        }
+    );
+}
+
+#[test]
+fn test_total_span() {
+    let mut codemap = CodeMap::new();
+    assert!(codemap.total_span().is_empty());
+
+    let f1 = codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_owned(),
+        "abcd\nefgh".to_owned(),
+    ));
+    let f2 = codemap.add_file(DefaultFileData::new(
+        "test2.rs".to_owned(),
+        "foo".to_owned(),
+    ));
+
+    let total = codemap.total_span();
+    assert_eq!(total.low(), f1.span.low());
+    assert_eq!(total.high(), f2.span.high());
+    assert!(total.contains(f1.span) && total.contains(f2.span));
+}
+
+#[test]
+fn test_snap_span() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "a°b汉c".to_owned(),
+    ));
+
+    // 1..3 is `°`, 4..7 is `汉`
+    let snapped = file.snap_span(file.span.subspan(2, 5));
+    assert_eq!(snapped, file.span.subspan(1, 7));
+    assert_eq!(file.source_slice(snapped), "°b汉");
+
+    let aligned = file.span.subspan(3, 7);
+    assert_eq!(file.snap_span(aligned), aligned);
+}
+
+#[test]
+fn test_editor_column() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "x\ty汉z\n\t\tw".to_owned(),
+    ));
+
+    assert_eq!(file.editor_column(file.span.low() + 1, 4), 1);
+    assert_eq!(file.editor_column(file.span.low() + 2, 4), 4);
+    assert_eq!(file.editor_column(file.span.low() + 3, 4), 5);
+    assert_eq!(file.editor_column(file.span.low() + 6, 4), 7);
+    assert_eq!(file.editor_column(file.span.low() + 6, 8), 11);
+    assert_eq!(file.editor_column(file.span.low() + 10, 4), 8);
+}
+
+#[test]
+fn test_look_up_byte_range() {
+    let mut codemap = CodeMap::new();
+    codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_owned(),
+        "abcd".to_owned(),
+    ));
+    let file = codemap.add_file(DefaultFileData::new(
+        "test2.rs".to_owned(),
+        "foo\nbar".to_owned(),
+    ));
+
+    let span = file.span.subspan(2, 5);
+    let range: Range<usize> = span.into();
+    assert_eq!(
+        codemap.look_up_byte_range(range),
+        codemap.look_up_span(span)
+    );
+}
+
+#[test]
+#[cfg(target_pointer_width = "64")]
+#[should_panic(expected = "does not fit")]
+fn test_look_up_byte_range_overflow() {
+    let mut codemap = CodeMap::new();
+    codemap.add_file(DefaultFileData::new(
+        "test.rs".to_owned(),
+        "abcd".to_owned(),
+    ));
+    codemap.look_up_byte_range(1..(u32::MAX as usize + 1));
+}
+
+#[test]
+fn test_line_starts() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "abc\n\ndef\n".to_owned(),
+    ));
+
+    let starts: Vec<_> = file.line_starts_enumerated().collect();
+    assert_eq!(starts.len(), file.num_lines());
+    assert_eq!(starts[0], (0, file.span.low()));
+    assert_eq!(
+        starts,
+        vec![
+            (0, file.span.low()),
+            (1, file.span.low() + 4),
+            (2, file.span.low() + 5),
+            (3, file.span.low() + 9),
+        ]
+    );
+
+    let newlines: Vec<_> = file.newline_positions().collect();
+    assert_eq!(
+        newlines,
+        vec![
+            file.span.low() + 3,
+            file.span.low() + 4,
+            file.span.low() + 8
+        ]
+    );
+}
+
+#[test]
+fn test_try_lookups() {
+    let mut codemap = CodeMap::new();
+    assert!(codemap.try_find_file(Pos(0)).is_none());
+
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "a°\nb".to_owned(),
+    ));
+    let outside = file.span.high() + 1;
+
+    assert!(codemap.try_find_file(file.span.low()).is_some());
+    assert!(codemap.try_find_file(outside).is_none());
+
+    assert_eq!(file.try_find_line(file.span.low() + 4), Some(1));
+    assert_eq!(file.try_find_line(outside), None);
+
+    assert_eq!(
+        file.try_find_line_col(file.span.low() + 3),
+        Some(LineCol { line: 0, column: 2 })
+    );
+    assert_eq!(file.try_find_line_col(file.span.low() + 2), None);
+    assert_eq!(file.try_find_line_col(outside), None);
+
+    assert_eq!(file.try_source_slice(file.span.subspan(1, 3)), Some("°"));
+    assert_eq!(file.try_source_slice(file.span.subspan(1, 2)), None);
+    assert_eq!(file.try_source_slice(file.span.with_high(outside)), None);
+
+    assert_eq!(file.span.try_subspan(1, 3), Some(file.span.subspan(1, 3)));
+    assert_eq!(file.span.try_subspan(3, 1), None);
+    assert_eq!(file.span.try_subspan(0, file.span.len() + 1), None);
+}
+
+#[test]
+fn test_offset_within_file() {
+    let mut codemap = CodeMap::new();
+    let f1 = codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_owned(),
+        "abcd".to_owned(),
+    ));
+    codemap.add_file(DefaultFileData::new(
+        "test2.rs".to_owned(),
+        "efgh".to_owned(),
+    ));
+
+    let pos = f1.span.low() + 1;
+    assert_eq!(codemap.offset_within_file(pos, 2), Some(f1.span.low() + 3));
+    assert_eq!(codemap.offset_within_file(pos, -1), Some(f1.span.low()));
+    assert_eq!(codemap.offset_within_file(pos, 3), Some(f1.span.high()));
+    assert_eq!(codemap.offset_within_file(pos, 5), None);
+    assert_eq!(codemap.offset_within_file(pos, -2), None);
+    assert_eq!(codemap.offset_within_file(Pos(100), 0), None);
+}
+
+#[test]
+fn test_line_cols_in() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "ab°\ncd".to_owned(),
+    ));
+
+    let span = file.span.subspan(1, 7);
+    let line_cols: Vec<_> = file.line_cols_in(span).collect();
+    assert_eq!(line_cols.len(), 5);
+    for &(pos, line_col) in &line_cols {
+        assert_eq!(line_col, file.find_line_col(pos));
+    }
+    assert_eq!(
+        line_cols[2],
+        (span.low() + 3, LineCol { line: 0, column: 3 })
+    );
+    assert_eq!(
+        line_cols[3],
+        (span.low() + 4, LineCol { line: 1, column: 0 })
+    );
+}
+
+#[test]
+fn test_source_slice_lossy() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new("<test>".to_owned(), "abc°".to_owned()));
+
+    let past_end = file.span.subspan(1, 5).with_high(file.span.high() + 3);
+    assert_eq!(file.source_slice_lossy(past_end), "bc°");
+    assert_eq!(
+        file.source_slice_lossy(file.span.subspan(2, 4)),
+        "c\u{FFFD}"
+    );
+
+    let before = Span::from(Pos(0)..file.span.low());
+    assert_eq!(file.source_slice_lossy(before), "");
+}
+
+#[test]
+fn test_stable_id() {
+    let mut codemap1 = CodeMap::new();
+    let mut codemap2 = CodeMap::new();
+    codemap2.add_file(DefaultFileData::new(
+        "other.rs".to_owned(),
+        "fn other() {}".to_owned(),
+    ));
+
+    let a = codemap1.add_file(DefaultFileData::new(
+        "main.rs".to_owned(),
+        "fn main() {}".to_owned(),
+    ));
+    let b = codemap2.add_file(DefaultFileData::new(
+        "main.rs".to_owned(),
+        "fn main() {}".to_owned(),
+    ));
+    let c = codemap2.add_file(DefaultFileData::new(
+        "main.rs".to_owned(),
+        "fn main() { }".to_owned(),
+    ));
+    let d = codemap2.add_file(DefaultFileData::new(
+        "main.r".to_owned(),
+        "sfn main() {}".to_owned(),
+    ));
+
+    assert_ne!(a.span, b.span);
+    assert_eq!(a.stable_id(), b.stable_id());
+    assert_eq!(a.stable_id(), 0x3185_93d7_546e_7794);
+    assert_ne!(a.stable_id(), c.stable_id());
+    assert_ne!(a.stable_id(), d.stable_id());
+}
+
+#[test]
+fn test_line_content_span() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "a \nxyz\r\n".to_owned(),
+    ));
+
+    assert_eq!(file.line_content_span(0), file.span.subspan(0, 2));
+    assert_eq!(file.line_content_span(1), file.span.subspan(3, 6));
+    assert_eq!(file.line_content_span(2), file.span.subspan(8, 8));
+    assert_eq!(file.source_slice(file.line_content_span(1)), "xyz");
+}
+
+#[test]
+fn test_char_count() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "65°00′N 18°00′W 汉语\n🔬".to_owned(),
+    ));
+
+    let span = file.span.subspan(21, 33);
+    assert_eq!(span.len(), 12);
+    assert_eq!(file.char_count(span), 5);
+    assert_eq!(file.char_count(file.span), 20);
+}
+
+#[test]
+#[should_panic(expected = "the CodeMap has no files")]
+fn test_look_up_span_empty_codemap() {
+    let codemap: CodeMap = CodeMap::new();
+    codemap.look_up_span(Span::from(Pos(1)..Pos(2)));
+}
+
+#[test]
+#[should_panic(expected = "the CodeMap has no files")]
+fn test_look_up_pos_empty_codemap() {
+    let codemap: CodeMap = CodeMap::new();
+    codemap.look_up_pos(Pos(1));
+}
+
+#[test]
+#[should_panic(expected = "unknown source location Pos(42)")]
+fn test_find_file_unknown_pos() {
+    let mut codemap = CodeMap::new();
+    codemap.add_file(DefaultFileData::new(
+        "test.rs".to_owned(),
+        "abcd".to_owned(),
+    ));
+    codemap.find_file(Pos(42));
+}
+
+#[test]
+fn test_subspan_local() {
+    let mut codemap = CodeMap::new();
+    codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_owned(),
+        "abcd".to_owned(),
+    ));
+    let file = codemap.add_file(DefaultFileData::new(
+        "test2.rs".to_owned(),
+        "foo\nbar".to_owned(),
+    ));
+
+    assert_eq!(file.full_span(), file.span);
+    let span = file.subspan_local(4, 7);
+    assert_eq!(file.source_slice(span), "bar");
+    assert_eq!(
+        codemap.look_up_span(span).begin,
+        LineCol { line: 1, column: 0 }
+    );
+}
+
+#[test]
+fn test_resolve_spanned() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "test.rs".to_owned(),
+        "let x = 1;\nlet y = 2;".to_owned(),
+    ));
+
+    let spanned = Spanned {
+        node: "y",
+        span: file.span.subspan(15, 16),
+    };
+    assert_eq!(
+        codemap.resolve_spanned(&spanned),
+        codemap.look_up_span(spanned.span)
+    );
+}
+
+#[test]
+fn test_pos_from_utf16() {
+    let mut codemap = CodeMap::new();
+    let content = "65°00′N 18°00′W 汉语\n🔬";
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        content.to_owned(),
+    ));
+
+    assert_eq!(
+        file.find_line_col_utf16(file.span.low() + 33),
+        LineCol { line: 1, column: 2 }
+    );
+    for (i, _) in content.char_indices().chain(Some((content.len(), ' '))) {
+        let pos = file.span.low() + i as u64;
+        let line_col = file.find_line_col_utf16(pos);
+        assert_eq!(
+            file.pos_from_utf16(line_col.line, line_col.column),
+            Some(pos)
+        );
+    }
+    // inside the surrogate pair of the emoji, past the end of a line, and past the last line
+    assert_eq!(file.pos_from_utf16(1, 1), None);
+    assert_eq!(file.pos_from_utf16(0, 19), None);
+    assert_eq!(file.pos_from_utf16(2, 0), None);
+}
+
+#[test]
+fn test_common_indent() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "fn f() {\n    a;\n        b;\n\n      c;\n}\n".to_owned(),
+    ));
+
+    let body = file.line_span(1).merge(file.line_span(4));
+    assert_eq!(file.common_indent(body), 4);
+    assert_eq!(file.common_indent(file.line_span(2)), 8);
+    assert_eq!(file.common_indent(file.line_span(3)), 0);
+    assert_eq!(file.common_indent(file.span), 0);
+}
+
+#[test]
+fn test_file_by_id() {
+    let mut codemap = CodeMap::new();
+    let f1 = codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_string(),
+        "abcd\nefghij\nqwerty".to_string(),
+    ));
+    let f2 = codemap.add_file(DefaultFileData::new(
+        "test2.rs".to_string(),
+        "foo\nbar".to_string(),
+    ));
+
+    assert_eq!(codemap.file_by_id(f1.id()), Some(&f1));
+    assert_eq!(codemap.file_by_id(f2.id()), Some(&f2));
+    assert_eq!(codemap.file_by_id(FileId(2)), None);
+}
+
+#[test]
+fn test_lines_opts() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "foo\nbar\n".to_owned(),
+    ));
+    assert_eq!(file.lines().collect::<Vec<_>>(), vec!["foo", "bar", ""]);
+    assert_eq!(
+        file.lines_opts(false).collect::<Vec<_>>(),
+        vec!["foo", "bar"]
+    );
+
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "foo\r\nbar".to_owned(),
+    ));
+    assert_eq!(
+        file.lines_opts(false).collect::<Vec<_>>(),
+        vec!["foo", "bar"]
+    );
+
+    let file = codemap.add_file(DefaultFileData::new("<test>".to_owned(), "".to_owned()));
+    assert_eq!(file.lines_opts(false).collect::<Vec<_>>(), vec![""]);
+}
+
+#[test]
+fn test_line_span_kind() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "a \nxyz\r\n".to_owned(),
+    ));
+
+    assert_eq!(
+        file.source_slice(file.line_span_kind(1, LineSpanKind::WithTerminator)),
+        "xyz\r\n"
+    );
+    assert_eq!(
+        file.source_slice(file.line_span_kind(1, LineSpanKind::ContentOnly)),
+        "xyz"
+    );
+}
+
+#[test]
+fn test_line_context() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "65°00′N 18°00′W 汉语\n🔬".to_owned(),
+    ));
+
+    assert_eq!(
+        file.line_context(file.span.low() + 21),
+        (0, "65°00′N 18°00′W 汉语", 15)
+    );
+    assert_eq!(file.line_context(file.span.low() + 29), (1, "🔬", 0));
+}
+
+#[test]
+fn test_find_str() {
+    let mut codemap = CodeMap::new();
+    codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_owned(),
+        "let x = 1;".to_owned(),
+    ));
+    let file = codemap.add_file(DefaultFileData::new(
+        "test2.rs".to_owned(),
+        "let y = x;\nlet z = x;".to_owned(),
+    ));
+
+    let span = file.find_str("x").unwrap();
+    assert_eq!(span, file.span.subspan(8, 9));
+    assert_eq!(codemap.find_file(span.low()).name(), "test2.rs");
+    assert_eq!(file.find_str("w"), None);
+
+    let all: Vec<_> = file.find_all_str("let").collect();
+    assert_eq!(
+        all,
+        vec![file.span.subspan(0, 3), file.span.subspan(11, 14)]
+    );
+}
+
+#[test]
+fn test_add_file_validated() {
+    let mut codemap = CodeMap::new();
+    let file = codemap
+        .add_file_validated(DefaultFileData::new(
+            "<test>".to_owned(),
+            "a°\nb".to_owned(),
+        ))
+        .unwrap();
+    assert_eq!(codemap.find_file(file.span.low()), &file);
+    assert_eq!(file.num_lines(), 2);
+}
+
+#[test]
+fn test_max_line_width() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "abc\n汉语汉语\r\nabcde\n".to_owned(),
+    ));
+    assert_eq!(file.max_line_width(), 5);
+    assert_eq!(file.max_line_display_width(), 8);
+    // cached
+    assert_eq!(file.max_line_width(), 5);
+
+    let file = codemap.add_file(DefaultFileData::new("<test>".to_owned(), "".to_owned()));
+    assert_eq!(file.max_line_width(), 0);
+}
+
+#[test]
+fn test_extend_span_to_line() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "one\ntwo\nthree".to_owned(),
+    ));
+
+    let two = file.span.subspan(5, 6);
+    assert_eq!(
+        codemap.extend_span_to_next_line(two),
+        file.span.subspan(5, 13)
+    );
+    assert_eq!(
+        codemap.extend_span_to_prev_line(two),
+        file.span.subspan(0, 6)
+    );
+
+    let line = file.line_span(1);
+    assert_eq!(
+        codemap.extend_span_to_next_line(line),
+        line.merge(file.line_span(2))
+    );
+
+    let first = file.span.subspan(1, 2);
+    assert_eq!(
+        codemap.extend_span_to_prev_line(first),
+        file.span.subspan(0, 2)
+    );
+    let last = file.span.subspan(9, 10);
+    assert_eq!(
+        codemap.extend_span_to_next_line(last),
+        file.span.subspan(9, 13)
+    );
+}
+
+#[test]
+#[cfg(feature = "rayon")]
+fn test_resolve_par() {
+    let mut codemap = CodeMap::new();
+    let f1 = codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_owned(),
+        "abcd\nefghij\nqwerty".to_owned(),
+    ));
+    let f2 = codemap.add_file(DefaultFileData::new(
+        "test2.rs".to_owned(),
+        "foo\nbar".to_owned(),
+    ));
+
+    let mut positions = vec![];
+    for i in (0..=f1.span.len()).rev() {
+        positions.push(f1.span.low() + i);
+        positions.push(f2.span.low() + i % (f2.span.len() + 1));
+    }
+    let serial: Vec<_> = positions
+        .iter()
+        .map(|&pos| codemap.look_up_pos(pos))
+        .collect();
+    assert_eq!(codemap.resolve_par(&positions), serial);
+}
+
+#[test]
+fn test_look_up_pos_bytes() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "65°00′N 18°00′W 汉语\n🔬".to_owned(),
+    ));
+
+    let (loc, byte_col) = codemap.look_up_pos_bytes(file.span.low() + 21);
+    assert_eq!(loc, codemap.look_up_pos(file.span.low() + 21));
+    assert_eq!((loc.position.column, byte_col), (15, 21));
+    let (loc, byte_col) = codemap.look_up_pos_bytes(file.span.low() + 33);
+    assert_eq!((loc.position.column, byte_col), (1, 4));
+}
+
+#[test]
+fn test_erased_codemap() {
+    struct StaticFileData {
+        name: &'static str,
+        source: &'static str,
+    }
+
+    impl FileData for StaticFileData {
+        type Source = str;
+        type Name = str;
+
+        fn source(&self) -> &str {
+            self.source
+        }
+
+        fn name(&self) -> &str {
+            self.name
+        }
+    }
+
+    let mut default_map = CodeMap::new();
+    let f1 = default_map.add_file(DefaultFileData::new(
+        "default.rs".to_owned(),
+        "foo\nbar".to_owned(),
+    ));
+    let mut static_map = CodeMap::new();
+    let f2 = static_map.add_file(StaticFileData {
+        name: "static.rs",
+        source: "baz",
+    });
+
+    let maps: Vec<Box<dyn ErasedCodeMap>> = vec![Box::new(default_map), Box::new(static_map)];
+    let spans = [f1.span.subspan(4, 7), f2.span.subspan(1, 2)];
+
+    assert_eq!(maps[0].file_name(spans[0].low()), "default.rs");
+    assert_eq!(maps[0].source_for_span(spans[0]), "bar");
+    assert_eq!(
+        maps[0].look_up_span(spans[0]),
+        OwnedSpanLoc {
+            file_name: "default.rs".to_owned(),
+            begin: LineCol { line: 1, column: 0 },
+            end: LineCol { line: 1, column: 3 },
+        }
+    );
+    assert_eq!(maps[1].file_name(spans[1].low()), "static.rs");
+    assert_eq!(maps[1].source_for_span(spans[1]), "a");
+    assert_eq!(
+        maps[1].look_up_span(spans[1]).to_string(),
+        "static.rs:1:2: 1:3"
+    );
+}
+
+#[test]
+fn test_is_single_line() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "abc\ndef".to_owned(),
+    ));
+
+    let single = file.span.subspan(0, 3);
+    assert!(codemap.is_single_line(single));
+    assert!(codemap.look_up_span(single).is_single_line());
+
+    assert_eq!(codemap.look_up_span(single.subspan(1, 3)).width(), Some(2));
+
+    let multi = file.span.subspan(2, 5);
+    assert!(!codemap.is_single_line(multi));
+    assert!(!codemap.look_up_span(multi).is_single_line());
+    assert_eq!(codemap.look_up_span(multi).width(), None);
+}
+
+#[test]
+fn test_paragraph_spans() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "\nFirst line\nsecond line\n  \n\nSecond paragraph\n".to_owned(),
+    ));
+
+    let paragraphs: Vec<_> = file
+        .paragraph_spans()
+        .map(|span| file.source_slice(span))
+        .collect();
+    assert_eq!(
+        paragraphs,
+        vec!["First line\nsecond line", "Second paragraph"]
+    );
+}
+
+#[test]
+fn test_name_string() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_string(),
+        "abcd\nefghij\nqwerty".to_string(),
+    ));
+
+    assert_eq!(file.name_string(), "test1.rs");
+}
+
+#[test]
+#[should_panic(expected = "begins in file test1.rs but ends outside of any file")]
+fn test_look_up_span_past_file_end() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_owned(),
+        "abcd".to_owned(),
+    ));
+    codemap.look_up_span(file.span.with_high(file.span.high() + 1));
+}
+
+#[test]
+#[should_panic(expected = "begins in file test1.rs but ends in file test2.rs")]
+fn test_look_up_span_across_files() {
+    let mut codemap = CodeMap::new();
+    let f1 = codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_owned(),
+        "abcd".to_owned(),
+    ));
+    let f2 = codemap.add_file(DefaultFileData::new(
+        "test2.rs".to_owned(),
+        "efgh".to_owned(),
+    ));
+    codemap.look_up_span(f1.span.merge(f2.span));
+}
+
+#[test]
+#[should_panic(expected = "begins in file test1.rs but ends in file test2.rs")]
+fn test_erased_source_for_span_across_files() {
+    let mut codemap = CodeMap::new();
+    let f1 = codemap.add_file(DefaultFileData::new(
+        "test1.rs".to_owned(),
+        "abcd".to_owned(),
+    ));
+    let f2 = codemap.add_file(DefaultFileData::new(
+        "test2.rs".to_owned(),
+        "efgh".to_owned(),
+    ));
+    let erased: &dyn ErasedCodeMap = &codemap;
+    erased.source_for_span(f1.span.merge(f2.span));
+}
+
+#[test]
+fn test_span_lines_text() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "let a = 1;\nlet b = a\n    + 2;\n".to_owned(),
+    ));
+
+    let span = file.span.subspan(19, 29);
+    assert_eq!(
+        codemap.span_lines_text(span),
+        vec!["let b = a\n", "    + 2;\n"]
+    );
+    assert_eq!(
+        codemap.span_lines_text(file.span.subspan(4, 5)),
+        vec!["let a = 1;\n"]
+    );
+}
+
+#[test]
+fn test_add_file_at() {
+    let mut codemap = CodeMap::new();
+    let a = codemap
+        .add_file_at(
+            DefaultFileData::new("a.rs".to_owned(), "abc".to_owned()),
+            Pos(100),
+        )
+        .unwrap();
+    assert_eq!(
+        a.span,
+        Span {
+            low: Pos(100),
+            high: Pos(103)
+        }
+    );
+    assert_eq!(codemap.total_span().high(), Pos(103));
+
+    // The range before `a` is reserved and can be filled later.
+    let b = codemap
+        .add_file_at(
+            DefaultFileData::new("b.rs".to_owned(), "hello".to_owned()),
+            Pos(10),
+        )
+        .unwrap();
+    assert_eq!(
+        b.span,
+        Span {
+            low: Pos(10),
+            high: Pos(15)
+        }
+    );
+    assert_eq!(codemap.find_file(Pos(12)).name(), "b.rs");
+    assert_eq!(codemap.find_file(Pos(101)).name(), "a.rs");
+
+    assert_eq!(
+        codemap
+            .add_file_at(
+                DefaultFileData::new("c.rs".to_owned(), "xyz".to_owned()),
+                Pos(98)
+            )
+            .unwrap_err(),
+        PlacementError::Overlaps(a.span)
+    );
+    assert_eq!(
+        codemap
+            .add_file_at(
+                DefaultFileData::new("c.rs".to_owned(), "xyz".to_owned()),
+                Pos(15)
+            )
+            .unwrap_err(),
+        PlacementError::Overlaps(b.span)
+    );
+    assert_eq!(
+        codemap
+            .add_file_at(
+                DefaultFileData::new("c.rs".to_owned(), "xyz".to_owned()),
+                Pos(u32::MAX - 1)
+            )
+            .unwrap_err(),
+        PlacementError::OutOfRange
+    );
+
+    let c = codemap.add_file(DefaultFileData::new("c.rs".to_owned(), "xyz".to_owned()));
+    assert_eq!(c.span.low(), Pos(104));
+}
+
+#[test]
+fn test_add_file_at_out_of_order() {
+    let mut codemap = CodeMap::new();
+    let placements = [
+        (300, "third"),
+        (10, "first"),
+        (200, "second"),
+        (400, "fourth"),
+    ];
+    let files: Vec<_> = placements
+        .iter()
+        .map(|&(low, name)| {
+            codemap
+                .add_file_at(
+                    DefaultFileData::new(name.to_owned(), "0123456789".to_owned()),
+                    Pos(low),
+                )
+                .unwrap()
+        })
+        .collect();
+
+    for file in &files {
+        for offset in 0..=10 {
+            let found = codemap.find_file(file.span.low() + offset);
+            assert_eq!(found.name(), file.name());
+            assert_eq!(found.id(), file.id());
+        }
+    }
+    assert!(codemap.try_find_file(Pos(100)).is_none());
+    assert!(codemap.try_find_file(Pos(350)).is_none());
+    assert_eq!(
+        codemap.total_span(),
+        Span {
+            low: Pos(10),
+            high: Pos(410)
+        }
+    );
+
+    let mut ids: Vec<_> = files.iter().map(|f| f.id()).collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), files.len());
+    assert_eq!(codemap.file_by_id(files[2].id()).unwrap().name(), "second");
+}
+
+#[test]
+fn test_eof_line_col() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new("a.rs".to_owned(), "foo\n".to_owned()));
+    assert_eq!(file.eof_pos(), file.span.high());
+    assert_eq!(file.eof_line_col(), LineCol { line: 1, column: 0 });
+
+    let file = codemap.add_file(DefaultFileData::new("b.rs".to_owned(), "foo".to_owned()));
+    assert_eq!(file.eof_line_col(), LineCol { line: 0, column: 3 });
+}
+
+#[test]
+fn test_from_sources() {
+    let (codemap, files) = CodeMap::from_sources(vec![
+        ("a.rs".to_owned(), "fn a() {}\n".to_owned()),
+        ("b.rs".to_owned(), "fn b() {}\nfn c() {}\n".to_owned()),
+    ]);
+    assert_eq!(files.len(), 2);
+    assert_eq!(
+        codemap
+            .look_up_pos(files[0].span.subspan(3, 4).low())
+            .to_string(),
+        "a.rs:1:4"
+    );
+    assert_eq!(
+        codemap
+            .look_up_pos(files[1].span.subspan(13, 14).low())
+            .to_string(),
+        "b.rs:2:4"
+    );
+}
+
+#[test]
+fn test_total_chars() {
+    let (codemap, _) = CodeMap::from_sources(vec![
+        ("a.txt".to_owned(), "65°00′N".to_owned()),
+        ("b.txt".to_owned(), "汉语\n🔬".to_owned()),
+    ]);
+    assert_eq!(codemap.total_bytes(), 10 + 11);
+    assert_eq!(codemap.total_chars(), 7 + 4);
+    assert_eq!(codemap.total_chars(), 7 + 4);
+}
+
+#[test]
+fn test_fmt_pos_and_span() {
+    struct PosDisplay<'a>(&'a CodeMap, Pos);
+    impl<'a> fmt::Display for PosDisplay<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+            self.0.fmt_pos(self.1, f)
+        }
+    }
+    struct SpanDisplay<'a>(&'a CodeMap, Span);
+    impl<'a> fmt::Display for SpanDisplay<'a> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+            self.0.fmt_span(self.1, f)
+        }
+    }
+
+    let (codemap, files) = CodeMap::from_sources(vec![
+        ("a.rs".to_owned(), "fn a() {}\n".to_owned()),
+        ("b.rs".to_owned(), "fn b() {}\nfn c() {}\n".to_owned()),
+    ]);
+    let pos = files[1].span.low() + 13;
+    assert_eq!(
+        PosDisplay(&codemap, pos).to_string(),
+        codemap.look_up_pos(pos).to_string()
+    );
+
+    for &(begin, end) in &[(3, 3), (3, 4), (3, 13)] {
+        let span = files[1].span.subspan(begin, end);
+        assert_eq!(
+            SpanDisplay(&codemap, span).to_string(),
+            codemap.look_up_span(span).to_string()
+        );
+    }
+}
+
+#[test]
+fn test_with_trailing_newline() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::with_trailing_newline(
+        "a.rs".to_owned(),
+        "foo\nbar".to_owned(),
+    ));
+    assert_eq!(file.source(), "foo\nbar\n");
+    assert_eq!(file.num_lines(), 3);
+
+    let file = codemap.add_file(DefaultFileData::with_trailing_newline(
+        "b.rs".to_owned(),
+        "foo\n".to_owned(),
+    ));
+    assert_eq!(file.source(), "foo\n");
+    assert_eq!(file.num_lines(), 2);
+}
+
+#[test]
+fn test_remap_file() {
+    let mut codemap = CodeMap::new();
+    let old = codemap.add_file(DefaultFileData::new(
+        "a.rs".to_owned(),
+        "let x = abc;\nfoo();".to_owned(),
+    ));
+    let new = codemap.add_file(DefaultFileData::new(
+        "a.rs".to_owned(),
+        "let x = 4;\nfoo();".to_owned(),
+    ));
+    let remap = codemap.remap_file(&old, &new);
+
+    for offset in 0..=8 {
+        assert_eq!(
+            remap(old.span.low() + offset),
+            Some(new.span.low() + offset)
+        );
+    }
+    assert_eq!(remap(old.span.low() + 9), None);
+    assert_eq!(remap(old.span.low() + 10), None);
+    for offset in 11..=19 {
+        assert_eq!(
+            remap(old.span.low() + offset),
+            Some(new.span.low() + (offset - 2))
+        );
+    }
+    assert_eq!(remap(new.span.low()), None);
+}
+
+#[test]
+fn test_file_index_range() {
+    let (codemap, files) = CodeMap::from_sources(vec![
+        ("a.rs".to_owned(), "aaaa".to_owned()),
+        ("b.rs".to_owned(), "bbbb".to_owned()),
+        ("c.rs".to_owned(), "cccc".to_owned()),
+    ]);
+    let span = files[1]
+        .span
+        .subspan(2, 4)
+        .merge(files[2].span.subspan(0, 1));
+    let range = codemap.file_index_range(span);
+    assert_eq!(range, 1..=2);
+    let names: Vec<_> = codemap.files()[range]
+        .iter()
+        .map(|f| f.name_string())
+        .collect();
+    assert_eq!(names, ["b.rs", "c.rs"]);
+
+    assert_eq!(codemap.file_index_range(files[0].span), 0..=0);
+    assert_eq!(codemap.file_index_range(codemap.total_span()), 0..=2);
+}
+
+#[test]
+fn test_find_line_span() {
+    let mut codemap = CodeMap::new();
+    let content = "a \nxyz\r\n";
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        content.to_owned(),
+    ));
+
+    for offset in 0..=content.len() as u64 {
+        let pos = file.span.low() + offset;
+        let line = file.find_line(pos);
+        assert_eq!(file.find_line_span(pos), (line, file.line_span(line)));
+    }
+}
+
+#[test]
+fn test_find_line_col_joined_emoji() {
+    let mut codemap = CodeMap::new();
+    let content = "a\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}b\u{2764}\u{FE0F}c";
+    let file = codemap.add_file(DefaultFileData::new(
+        "emoji.txt".to_owned(),
+        content.to_owned(),
+    ));
+    let b = file.span.low() + content.find('b').unwrap() as u64;
+    let c = file.span.low() + content.find('c').unwrap() as u64;
+
+    assert_eq!(file.find_line_col_with_mode(b, ColumnMode::Chars).column, 6);
+    assert_eq!(
+        file.find_line_col_with_mode(b, ColumnMode::JoinedEmoji)
+            .column,
+        2
+    );
+    assert_eq!(file.find_line_col_with_mode(c, ColumnMode::Chars).column, 9);
+    assert_eq!(
+        file.find_line_col_with_mode(c, ColumnMode::JoinedEmoji)
+            .column,
+        4
+    );
+    assert_eq!(
+        file.find_line_col_with_mode(file.span.high(), ColumnMode::JoinedEmoji),
+        LineCol { line: 0, column: 5 }
+    );
+}
+
+#[test]
+fn test_look_up_span_multi() {
+    let (codemap, files) = CodeMap::from_sources(vec![
+        ("a.rs".to_owned(), "aa\naa".to_owned()),
+        ("b.rs".to_owned(), "bb\nbb".to_owned()),
+    ]);
+    let span = files[0]
+        .span
+        .subspan(4, 5)
+        .merge(files[1].span.subspan(0, 4));
+    let locs = codemap.look_up_span_multi(span);
+    assert_eq!(locs.len(), 2);
+    assert_eq!(locs[0].file.name(), "a.rs");
+    assert_eq!(locs[0].begin, LineCol { line: 1, column: 1 });
+    assert_eq!(locs[0].end, LineCol { line: 1, column: 2 });
+    assert_eq!(locs[1].file.name(), "b.rs");
+    assert_eq!(locs[1].begin, LineCol { line: 0, column: 0 });
+    assert_eq!(locs[1].end, LineCol { line: 1, column: 1 });
+
+    let span = files[1].span.subspan(1, 2);
+    assert_eq!(
+        codemap.look_up_span_multi(span),
+        vec![codemap.look_up_span(span)]
+    );
+}
+
+#[test]
+fn test_look_up_tagged() {
+    let (codemap, files) = CodeMap::from_sources(vec![("a.rs".to_owned(), "abc".to_owned())]);
+    let pos = codemap.tag(files[0].span.low() + 1);
+    assert_eq!(codemap.look_up_tagged(pos).to_string(), "a.rs:1:2");
+    assert_ne!(codemap.id(), CodeMap::<DefaultFileData>::new().id());
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "belongs to a different CodeMap")]
+fn test_look_up_tagged_wrong_map() {
+    let (codemap, files) = CodeMap::from_sources(vec![("a.rs".to_owned(), "abc".to_owned())]);
+    let (other, _) = CodeMap::from_sources(vec![("b.rs".to_owned(), "abc".to_owned())]);
+    other.look_up_tagged(codemap.tag(files[0].span.low()));
+}
+
+#[test]
+fn test_numbered_source() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "a.rs".to_owned(),
+        "foo\nbar\n".to_owned(),
+    ));
+    assert_eq!(file.numbered_source(), "1 | foo\n2 | bar\n");
+
+    let source: String = (1..=10).map(|i| format!("line{}\n", i)).collect();
+    let file = codemap.add_file(DefaultFileData::new("b.rs".to_owned(), source));
+    let numbered = file.numbered_source();
+    assert!(numbered.starts_with(" 1 | line1\n 2 | line2\n"));
+    assert!(numbered.ends_with(" 9 | line9\n10 | line10\n"));
+}
+
+#[test]
+fn test_with_file_mut() {
+    fn contents(source: &str) -> DefaultFileData {
+        DefaultFileData::new("a.rs".to_owned(), source.to_owned())
+    }
+
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(contents("foo"));
+    let (id, ptr) = (file.id(), Arc::as_ptr(&file));
+    drop(file);
+
+    // The `CodeMap` holds the only reference, so the file is updated in place.
+    let file = codemap.with_file_mut(id, |data| *data = contents("foo\nbar"));
+    assert_eq!(Arc::as_ptr(&file), ptr);
+    assert_eq!(file.num_lines(), 2);
+    assert_eq!(
+        codemap.look_up_pos(file.span.low() + 5).to_string(),
+        "a.rs:2:2"
+    );
+
+    // An outstanding reference keeps the old contents.
+    let old = file;
+    let file = codemap.with_file_mut(id, |data| *data = contents("x"));
+    assert_eq!(old.source(), "foo\nbar");
+    assert_eq!(file.source(), "x");
+    assert_eq!(file.span.len(), 1);
+    assert_eq!(file.id(), id);
+    assert!(Arc::ptr_eq(codemap.file_by_id(id).unwrap(), &file));
+
+    // Growing a file into the next one fails without changing the map, whether or not the
+    // file is shared.
+    let next = codemap.add_file(DefaultFileData::new("b.rs".to_owned(), "bar".to_owned()));
+    let grow_and_check = |codemap: &mut CodeMap| {
+        let grow = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            codemap.with_file_mut(id, |data| *data = contents(&"x".repeat(20)))
+        }));
+        assert!(grow.is_err());
+        assert_eq!(codemap.check_invariants(), Ok(()));
+        let file = codemap.file_by_id(id).unwrap();
+        assert_eq!((file.source().as_ref(), file.span.len()), ("x", 1));
+        assert_eq!(codemap.find_file(next.span.low()).name(), "b.rs");
+    };
+    grow_and_check(&mut codemap);
+    drop(file);
+    grow_and_check(&mut codemap);
+}
+
+#[test]
+fn test_span_from_char_range() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "65°00′N 18°00′W 汉语\n🔬".to_owned(),
+    ));
+
+    assert_eq!(
+        file.span_from_char_range(16, 18),
+        Some(file.span.subspan(22, 28))
+    );
+    assert_eq!(
+        file.span_from_char_range(19, 20),
+        Some(file.span.subspan(29, 33))
+    );
+    assert_eq!(
+        file.span_from_char_range(20, 20),
+        Some(file.span.subspan(33, 33))
+    );
+    assert_eq!(file.span_from_char_range(19, 21), None);
+    assert_eq!(file.span_from_char_range(5, 4), None);
+}
+
+#[test]
+fn test_partition_valid_spans() {
+    let (codemap, files) = CodeMap::from_sources(vec![
+        ("a.rs".to_owned(), "aaaa".to_owned()),
+        ("b.rs".to_owned(), "bbbb".to_owned()),
+    ]);
+    let in_a = files[0].span.subspan(1, 3);
+    let in_b = files[1].span;
+    let cross_file = files[0]
+        .span
+        .subspan(2, 4)
+        .merge(files[1].span.subspan(0, 1));
+    let out_of_range = Span::from_start_len(files[1].span.high(), 2);
+    let before_files = Span::from(Pos(0)..Pos(0));
+
+    let (valid, invalid) =
+        codemap.partition_valid_spans(&[in_a, cross_file, out_of_range, in_b, before_files]);
+    assert_eq!(valid, [in_a, in_b]);
+    assert_eq!(invalid, [cross_file, out_of_range, before_files]);
+}
+
+#[test]
+fn test_first_and_last_line_span() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "a.rs".to_owned(),
+        "foo\nbar\nbaz".to_owned(),
+    ));
+    assert_eq!(file.first_line_span(), file.span.subspan(0, 4));
+    assert_eq!(file.last_line_span(), file.span.subspan(8, 11));
+
+    let empty = codemap.add_file(DefaultFileData::new("b.rs".to_owned(), String::new()));
+    assert_eq!(empty.first_line_span(), empty.span);
+    assert_eq!(empty.last_line_span(), empty.span);
+}
+
+#[test]
+fn test_spanned_lines() {
+    let mut codemap = CodeMap::new();
+    let content = "a \nxyz\r\n";
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        content.to_owned(),
+    ));
+
+    let mut rebuilt = String::new();
+    for (i, line) in file.spanned_lines().enumerate() {
+        assert_eq!(file.source_slice(line.span), line.node);
+        let terminator = line
+            .span
+            .with_low(line.span.high())
+            .with_high(file.line_span(i).high());
+        rebuilt.push_str(line.node);
+        rebuilt.push_str(file.source_slice(terminator));
+    }
+    assert_eq!(rebuilt, content);
+}
+
+#[test]
+fn test_resolve_light() {
+    let (codemap, files) = CodeMap::from_sources(vec![
+        ("a.rs".to_owned(), "fn a() {}\n".to_owned()),
+        ("b.rs".to_owned(), "fn b() {}\nfn c() {}\n".to_owned()),
+    ]);
+    for file in &files {
+        for offset in 0..=file.span.len() {
+            let pos = file.span.low() + offset;
+            assert_eq!(
+                codemap.resolve_light(pos),
+                (file.id(), codemap.look_up_pos(pos).position)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_add_file_with_lines() {
+    let mut codemap = CodeMap::new();
+    let low = codemap.total_span().high() + 1;
+    let file = codemap.add_file_with_lines(
+        DefaultFileData::new("a.bin".to_owned(), "aaaa;bb;cccccc".to_owned()),
+        vec![low, low + 5, low + 8],
+    );
+    assert_eq!(file.num_lines(), 3);
+    assert_eq!(file.source_line(1), "bb;");
+    assert_eq!(
+        codemap.look_up_pos(low + 9).position,
+        LineCol { line: 2, column: 1 }
+    );
+
+    let file = codemap.add_file(DefaultFileData::new("b.rs".to_owned(), "x".to_owned()));
+    assert_eq!(codemap.find_file(file.span.low()).name(), "b.rs");
+}
+
+#[test]
+fn test_add_file_with_lines_newlines() {
+    let mut codemap = CodeMap::new();
+    let low = codemap.total_span().high() + 1;
+    let file = codemap.add_file_with_lines(
+        DefaultFileData::new("a.bin".to_owned(), "aa\naa;bb".to_owned()),
+        vec![low, low + 6],
+    );
+    // The custom line table splits at `;`, but only the `\n` is a newline.
+    assert_eq!(file.source_line(0), "aa\naa;");
+    assert_eq!(file.newline_positions().collect::<Vec<_>>(), vec![low + 2]);
+
+    let id = file.id();
+    drop(file);
+    let file = codemap.with_file_mut(id, |data| {
+        *data = DefaultFileData::new("a.bin".to_owned(), "aa\naa;bbb".to_owned())
+    });
+    assert_eq!(file.line_starts(), &[low, low + 3][..]);
+    assert_eq!(file.newline_positions().collect::<Vec<_>>(), vec![low + 2]);
+}
+
+#[test]
+#[should_panic(expected = "not strictly increasing")]
+fn test_add_file_with_lines_unsorted() {
+    let mut codemap = CodeMap::new();
+    let low = codemap.total_span().high() + 1;
+    codemap.add_file_with_lines(
+        DefaultFileData::new("a.bin".to_owned(), "aaaa".to_owned()),
+        vec![low, low + 3, low + 2],
+    );
+}
+
+#[test]
+fn test_split_span_at_first_newline() {
+    let (codemap, files) =
+        CodeMap::from_sources(vec![("a.rs".to_owned(), "foo\nbar\nbaz\n".to_owned())]);
+    let file = &files[0];
+    let span = file.span.subspan(1, 10);
+    assert_eq!(
+        codemap.split_span_at_first_newline(span),
+        (file.span.subspan(1, 4), Some(file.span.subspan(4, 10)))
+    );
+
+    let line = file.span.subspan(4, 8);
+    assert_eq!(codemap.split_span_at_first_newline(line), (line, None));
+    let word = file.span.subspan(4, 7);
+    assert_eq!(codemap.split_span_at_first_newline(word), (word, None));
+}
+
+#[test]
+fn test_enclosing_block_span() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "a.rs".to_owned(),
+        "fn f() {\n    if x {\n        a();\n        b();\n    }\n}\n".to_owned(),
+    ));
+    let children = [
+        file.line_content_span(3).subspan(8, 11),
+        file.line_content_span(1).subspan(7, 8),
+        file.line_content_span(2).subspan(8, 9),
+        file.line_content_span(4).subspan(4, 5),
+    ];
+    let block = file.enclosing_block_span(&children).unwrap();
+    assert_eq!(block, file.line_span(1).merge(file.line_span(4)));
+    assert_eq!(
+        file.source_slice(block),
+        "    if x {\n        a();\n        b();\n    }\n"
+    );
+    assert_eq!(file.enclosing_block_span(&[]), None);
+
+    assert_eq!(file.expand_to_lines(file.line_span(2)), file.line_span(2));
+}
+
+#[test]
+fn test_ends_with_newline() {
+    let (_, files) = CodeMap::from_sources(vec![
+        ("lf.rs".to_owned(), "foo\n".to_owned()),
+        ("crlf.rs".to_owned(), "foo\r\n".to_owned()),
+        ("none.rs".to_owned(), "foo".to_owned()),
+        ("empty.rs".to_owned(), String::new()),
+    ]);
+    let ends: Vec<_> = files.iter().map(|f| f.ends_with_newline()).collect();
+    assert_eq!(ends, [true, true, false, false]);
+}
+
+#[test]
+fn test_format_pos() {
+    let (codemap, files) = CodeMap::from_sources(vec![(
+        "a.rs".to_owned(),
+        "fn a() {}\nfn b() {}\n".to_owned(),
+    )]);
+    let pos = files[0].span.low() + 13;
+    assert_eq!(codemap.format_pos(pos, PosFormat::Colon), "a.rs:2:4");
+    assert_eq!(
+        codemap.format_pos(pos, PosFormat::Colon),
+        codemap.look_up_pos(pos).to_string()
+    );
+    assert_eq!(codemap.format_pos(pos, PosFormat::Parens), "a.rs(2,4)");
+    assert_eq!(codemap.format_pos(pos, PosFormat::EditorLine), "a.rs +2");
+}
+
+#[test]
+fn test_char_len_of_span() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "65°00′N 18°00′W 汉语\n🔬".to_owned(),
+    ));
+
+    assert_eq!(codemap.char_len_of_span(file.span.subspan(21, 33)), 5);
+    assert_eq!(codemap.char_len_of_span(file.span), 20);
+}
+
+#[test]
+fn test_compact() {
+    fn data(name: &str, source: &str) -> DefaultFileData {
+        DefaultFileData::new(name.to_owned(), source.to_owned())
+    }
+
+    let mut codemap = CodeMap::new();
+    codemap
+        .add_file_at(data("a.rs", "abc\ndef"), Pos(100))
+        .unwrap();
+    let b = codemap.add_file_at(data("b.rs", "xyz"), Pos(500)).unwrap();
+    assert_eq!(codemap.total_span(), Span::from(Pos(100)..Pos(503)));
+
+    let remap = codemap.compact();
+    assert_eq!(codemap.total_span(), Span::from(Pos(1)..Pos(12)));
+    assert_eq!(remap(Pos(105)), Some(Pos(6)));
+    assert_eq!(remap(Pos(501)), Some(Pos(10)));
+    assert_eq!(remap(Pos(300)), None);
+    assert_eq!(codemap.look_up_pos(Pos(6)).to_string(), "a.rs:2:2");
+    assert_eq!(codemap.look_up_pos(Pos(10)).to_string(), "b.rs:1:2");
+
+    // `b` was still referenced, so it was copied rather than moved.
+    assert_eq!(b.span.low(), Pos(500));
+    let c = codemap.add_file(data("c.rs", "c"));
+    assert_eq!(c.span.low(), Pos(13));
+}
+
+#[test]
+fn test_span_loc_to_owned_location() {
+    let (codemap, files) = CodeMap::from_sources(vec![(
+        "a.rs".to_owned(),
+        "fn a() {}\nfn b() {}\n".to_owned(),
+    )]);
+    let loc = codemap.look_up_span(files[0].span.subspan(3, 13));
+    let owned = loc.to_owned_location();
+    assert_eq!(owned.file_name, "a.rs");
+    assert_eq!(owned.begin, LineCol { line: 0, column: 3 });
+    assert_eq!(owned.end, LineCol { line: 1, column: 3 });
+    assert_eq!(owned.to_string(), loc.to_string());
+
+    let handle = std::thread::spawn(move || owned.to_string());
+    assert_eq!(handle.join().unwrap(), "a.rs:1:4: 2:4");
+}
+
+#[test]
+fn test_add_files() {
+    let mut codemap = CodeMap::new();
+    let files = codemap.add_files(vec![
+        DefaultFileData::new("a.rs".to_owned(), "aaa".to_owned()),
+        DefaultFileData::new("b.rs".to_owned(), "bbb".to_owned()),
+        DefaultFileData::new("c.rs".to_owned(), "ccc".to_owned()),
+        DefaultFileData::new("b.rs".to_owned(), "new".to_owned()),
+    ]);
+    assert_eq!(files.len(), 3);
+    assert_eq!(codemap.files().len(), 4);
+    for name in &["a.rs", "c.rs"] {
+        let file = &files[*name];
+        assert_eq!(file.name(), *name);
+        assert_eq!(codemap.find_file(file.span.low()).name(), *name);
+    }
+    assert_eq!(files["b.rs"].source(), "new");
+}
+
+#[test]
+fn test_positions_of() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "data.csv".to_owned(),
+        "name,age\nAda,36,€\n".to_owned(),
+    ));
+    let row = file.line_content_span(1);
+    assert_eq!(
+        file.positions_of(row, ','),
+        vec![row.low() + 3, row.low() + 6]
+    );
+    assert_eq!(
+        file.positions_of(file.span, ','),
+        vec![file.span.low() + 4, row.low() + 3, row.low() + 6]
+    );
+    assert_eq!(file.positions_of(file.span, '€'), vec![row.low() + 7]);
+    assert_eq!(file.positions_of(row, ';'), vec![]);
+}
+
+#[test]
+fn test_context_line_for_pos() {
+    let (codemap, files) = CodeMap::from_sources(vec![(
+        "a.rs".to_owned(),
+        "let x = 1\nlet y = 2;\n".to_owned(),
+    )]);
+    let file = &files[0];
+
+    let (found, line, text) = codemap.context_line_for_pos(file.span.low() + 9);
+    assert!(Arc::ptr_eq(&found, file));
+    assert_eq!((line, &text[..]), (0, "let x = 1"));
+
+    let (_, line, text) = codemap.context_line_for_pos(file.span.low() + 10);
+    assert_eq!((line, &text[..]), (1, "let y = 2;"));
+    let (_, line, text) = codemap.context_line_for_pos(file.span.high());
+    assert_eq!((line, &text[..]), (2, ""));
+}
+
+#[test]
+fn test_exclude_terminator() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "a \nxyz\r\n".to_owned(),
+    ));
+
+    let with_crlf = file.span.subspan(4, 8);
+    assert_eq!(file.source_slice(with_crlf), "yz\r\n");
+    assert_eq!(file.exclude_terminator(with_crlf), file.span.subspan(4, 6));
+    assert_eq!(
+        file.exclude_terminator(file.span.subspan(0, 3)),
+        file.span.subspan(0, 2)
+    );
+    assert_eq!(
+        file.exclude_terminator(file.span.subspan(4, 6)),
+        file.span.subspan(4, 6)
+    );
+    assert_eq!(
+        file.exclude_terminator(file.span.subspan(7, 8)),
+        file.span.subspan(7, 7)
+    );
+}
+
+#[test]
+fn test_sub_file() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "a.rs".to_owned(),
+        "let s = \"héllo\n{name}\";\n".to_owned(),
+    ));
+    let literal = file.find_str("héllo\n{name}").unwrap();
+    let sub = file.sub_file(literal);
+    assert_eq!(sub.text(), "héllo\n{name}");
+    assert_eq!(sub.span(), literal);
+
+    let name = Pos(sub.text().find("name").unwrap() as u32);
+    assert_eq!(sub.find_line_col(name), LineCol { line: 1, column: 1 });
+    let parent = sub.to_parent(name);
+    assert_eq!(file.source_slice(Span::from_start_len(parent, 4)), "name");
+    assert_eq!(file.find_line_col(parent), LineCol { line: 1, column: 1 });
+
+    assert_eq!(sub.find_line_col(Pos(6)), LineCol { line: 0, column: 5 });
+    assert_eq!(sub.to_parent(Pos(0)), literal.low());
+}
+
+#[test]
+fn test_line_char_and_display_width() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "<test>".to_owned(),
+        "65°00′N 18°00′W 汉语\n🔬".to_owned(),
+    ));
+
+    assert_eq!(file.source_line(0).len(), 28);
+    assert_eq!(file.line_char_width(0), 18);
+    assert_eq!(file.line_display_width(0), 20);
+    assert_eq!(file.line_char_width(1), 1);
+    assert_eq!(file.line_display_width(1), 2);
+}
+
+#[test]
+fn test_file_name_of() {
+    let (codemap, files) = CodeMap::from_sources(vec![
+        ("a.rs".to_owned(), "aaa".to_owned()),
+        ("b.rs".to_owned(), "bbb".to_owned()),
+    ]);
+    assert_eq!(codemap.file_name_of(files[0].span.low() + 1), Some("a.rs"));
+    assert_eq!(codemap.file_name_of(files[1].span.high()), Some("b.rs"));
+    assert_eq!(codemap.file_name_of(Pos(1000)), None);
+}
+
+#[test]
+fn test_blank_line_spans() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "a.rs".to_owned(),
+        "\nfn a() {}\n  \t\n\r\nfn b() {}\n\n".to_owned(),
+    ));
+    let blank: Vec<_> = file.blank_line_spans().collect();
+    assert_eq!(
+        blank,
+        vec![
+            (0, file.span.subspan(0, 0)),
+            (2, file.span.subspan(11, 14)),
+            (3, file.span.subspan(15, 15)),
+            (5, file.span.subspan(27, 27)),
+        ]
+    );
+}
+
+#[test]
+fn test_enclosing_lines() {
+    let mut codemap = CodeMap::new();
+    let a = codemap.add_file(DefaultFileData::new(
+        "a.rs".to_owned(),
+        "fn a() {\n    x;\n    y;\n}\n".to_owned(),
+    ));
+    let b = codemap.add_file(DefaultFileData::new("b.rs".to_owned(), "z\n".to_owned()));
+
+    let x = a.span.low() + 13;
+    let y = a.span.low() + 20;
+    assert_eq!(codemap.enclosing_lines(x, y), Some(a.span.subspan(9, 23)));
+    assert_eq!(codemap.enclosing_lines(y, x), Some(a.span.subspan(9, 23)));
+    assert_eq!(codemap.enclosing_lines(x, x), Some(a.span.subspan(9, 16)));
+    assert_eq!(
+        codemap.enclosing_lines(a.span.low(), a.span.high()),
+        Some(a.span)
+    );
+    assert_eq!(codemap.enclosing_lines(x, b.span.low()), None);
+    assert_eq!(codemap.enclosing_lines(b.span.low(), x), None);
+}
+
+#[test]
+fn test_lines_around() {
+    let mut codemap = CodeMap::new();
+    let file = codemap.add_file(DefaultFileData::new(
+        "a.txt".to_owned(),
+        "zero\none\ntwo\r\nthree\nfour".to_owned(),
+    ));
+
+    let two = file.span.low() + 10;
+    assert_eq!(
+        codemap.lines_around(two, 1),
+        vec![(1, "one"), (2, "two"), (3, "three")]
+    );
+    assert_eq!(codemap.lines_around(two, 0), vec![(2, "two")]);
+    assert_eq!(
+        codemap.lines_around(file.span.low(), 2),
+        vec![(0, "zero"), (1, "one"), (2, "two")]
+    );
+    assert_eq!(
+        codemap.lines_around(file.span.high(), 1),
+        vec![(3, "three"), (4, "four")]
+    );
+    assert_eq!(codemap.lines_around(two, usize::MAX).len(), 5);
+}
+
+#[test]
+fn test_check_invariants() {
+    fn lines(codemap: &mut CodeMap) -> &mut Vec<Pos> {
+        &mut Arc::get_mut(&mut codemap.files[0]).unwrap().lines
+    }
+    fn file(codemap: &mut CodeMap, name: &str, contents: &str) -> Arc<File<DefaultFileData>> {
+        codemap.add_file(DefaultFileData::new(name.to_owned(), contents.to_owned()))
+    }
+
+    let mut codemap = CodeMap::new();
+    assert_eq!(codemap.check_invariants(), Ok(()));
+    file(&mut codemap, "a.rs", "a\nb\n");
+    file(&mut codemap, "b.rs", "");
+    let c = codemap
+        .add_file_at(
+            DefaultFileData::new("c.rs".to_owned(), "c".to_owned()),
+            Pos(100),
+        )
+        .unwrap();
+    assert_eq!(codemap.check_invariants(), Ok(()));
+
+    let mut unsorted = CodeMap::new();
+    file(&mut unsorted, "a.rs", "a");
+    file(&mut unsorted, "b.rs", "b");
+    unsorted.files.swap(0, 1);
+    assert_eq!(
+        unsorted.check_invariants(),
+        Err("file b.rs at Span(3..4) is not before file a.rs at Span(1..2)".to_owned())
+    );
+
+    codemap.end_pos = Pos(50);
+    assert!(codemap
+        .check_invariants()
+        .unwrap_err()
+        .starts_with("end position Pos(50) is before the end of file c.rs"));
+    codemap.end_pos = c.span.high();
+
+    lines(&mut codemap).swap(1, 2);
+    assert_eq!(
+        codemap.check_invariants(),
+        Err("line table of file a.rs is not strictly increasing at Pos(3)".to_owned())
+    );
+    lines(&mut codemap).swap(1, 2);
+    lines(&mut codemap).push(Pos(10));
+    assert!(codemap
+        .check_invariants()
+        .unwrap_err()
+        .starts_with("line start Pos(10) is past the end of file a.rs"));
+    lines(&mut codemap).clear();
+    assert!(codemap
+        .check_invariants()
+        .unwrap_err()
+        .starts_with("line table of file a.rs does not begin at Pos(1)"));
+}
@@ -1,4 +1,5 @@
 use std::cmp;
+use std::fmt;
 use std::ops::{Add, Deref, Sub};
 
 /// A small, `Copy`, value representing a position in a `CodeMap`'s file.
@@ -21,7 +22,7 @@ impl Sub<Pos> for Pos {
 }
 
 /// A range of text within a CodeMap.
-#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Hash, Eq, PartialEq)]
 pub struct Span {
     /// The position in the codemap representing the first byte of the span.
     pub(crate) low: Pos,
@@ -30,6 +31,13 @@ pub struct Span {
     pub(crate) high: Pos,
 }
 
+impl fmt::Debug for Span {
+    /// Formats the span as `Span(low..high)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Span({}..{})", self.low.0, self.high.0)
+    }
+}
+
 // compatibility with other libraries that expect `Span`s to be constructed from a `Range`
 impl From<std::ops::Range<Pos>> for Span {
     fn from(r: std::ops::Range<Pos>) -> Self {
@@ -44,6 +52,21 @@ impl From<Span> for std::ops::Range<usize> {
 }
 
 impl Span {
+    /// Makes a span starting at `start` and extending for `len` bytes.
+    ///
+    /// # Panics
+    ///   * If the end of the span does not fit in a `Pos`
+    pub const fn from_start_len(start: Pos, len: u64) -> Span {
+        assert!(
+            len <= (u32::MAX - start.0) as u64,
+            "span end does not fit in a `Pos`"
+        );
+        Span {
+            low: start,
+            high: Pos(start.0 + len as u32),
+        }
+    }
+
     /// Makes a span from offsets relative to the start of this span.
     ///
     /// # Panics
@@ -58,11 +81,40 @@ impl Span {
         }
     }
 
+    /// Makes a span from offsets relative to the start of this span, or returns `None` if
+    /// `subspan` would panic.
+    pub const fn try_subspan(&self, begin: u64, end: u64) -> Option<Span> {
+        if end < begin || end > self.len() {
+            return None;
+        }
+        Some(Span {
+            low: Pos(self.low.0 + begin as u32),
+            high: Pos(self.low.0 + end as u32),
+        })
+    }
+
     /// Checks if a span is contained within this span.
     pub const fn contains(&self, other: Span) -> bool {
         self.low.0 <= other.low.0 && self.high.0 >= other.high.0
     }
 
+    /// Checks if a position is within this span, treating it as the half-open range
+    /// `low..high`.
+    ///
+    /// `high` is not included, so an empty span contains no positions. See
+    /// `contains_pos_inclusive` to include `high`.
+    pub const fn contains_pos(&self, pos: Pos) -> bool {
+        self.low.0 <= pos.0 && pos.0 < self.high.0
+    }
+
+    /// Checks if a position is within this span, treating it as the closed range `low..=high`.
+    ///
+    /// Unlike `contains_pos`, `high` is included. This is useful where the end position is
+    /// meaningful on its own, such as a caret at the end of a file.
+    pub const fn contains_pos_inclusive(&self, pos: Pos) -> bool {
+        self.low.0 <= pos.0 && pos.0 <= self.high.0
+    }
+
     /// The position in the codemap representing the first byte of the span.
     pub const fn low(&self) -> Pos {
         self.low
@@ -73,16 +125,137 @@ impl Span {
         self.high
     }
 
+    /// Returns a copy of this span with `low` replaced.
+    ///
+    /// In debug builds, panics if `low` is after `self.high()`.
+    pub const fn with_low(&self, low: Pos) -> Span {
+        debug_assert!(low.0 <= self.high.0);
+        Span {
+            low,
+            high: self.high,
+        }
+    }
+
+    /// Returns a copy of this span with `high` replaced.
+    ///
+    /// In debug builds, panics if `high` is before `self.low()`.
+    pub const fn with_high(&self, high: Pos) -> Span {
+        debug_assert!(self.low.0 <= high.0);
+        Span {
+            low: self.low,
+            high,
+        }
+    }
+
+    /// Returns a span with the same `low` as this one and a length of `len` bytes.
+    ///
+    /// # Panics
+    ///   * If the end of the span does not fit in a `Pos`
+    pub const fn with_len(&self, len: u64) -> Span {
+        Span::from_start_len(self.low, len)
+    }
+
+    /// Packs the span into a `u64`, with `low` in the high 32 bits and `high` in the low 32 bits.
+    ///
+    /// Comparing packed spans orders them by `low`, then by `high`.
+    pub const fn to_u64(&self) -> u64 {
+        (self.low.0 as u64) << 32 | self.high.0 as u64
+    }
+
+    /// Unpacks a span packed with `to_u64`.
+    ///
+    /// In debug builds, panics if the unpacked `low` is after `high`.
+    pub const fn from_u64(v: u64) -> Span {
+        let span = Span {
+            low: Pos((v >> 32) as u32),
+            high: Pos(v as u32),
+        };
+        debug_assert!(span.low.0 <= span.high.0);
+        span
+    }
+
+    /// Formats the span's positions as `low..high`, for example `5..10`.
+    ///
+    /// This can be parsed back with `from_display_string`.
+    pub fn to_display_string(&self) -> String {
+        format!("{}..{}", self.low.0, self.high.0)
+    }
+
+    /// Parses a span formatted with `to_display_string`.
+    ///
+    /// Returns `None` if `s` is not of the form `low..high`, or if `high` is before `low`.
+    pub fn from_display_string(s: &str) -> Option<Span> {
+        let (low, high) = s.split_once("..")?;
+        let low = Pos(low.parse().ok()?);
+        let high = Pos(high.parse().ok()?);
+        if high < low {
+            return None;
+        }
+        Some(Span { low, high })
+    }
+
     /// The length in bytes of the text of the span
     pub const fn len(&self) -> u64 {
         (self.high.0 - self.low.0) as u64
     }
 
+    /// Returns a span at least `min` bytes long, extending `high` if the span is shorter.
+    ///
+    /// This is useful for giving an empty span a visible width when rendering it. The result may
+    /// extend past the end of the file, so callers must clamp it to the file's bounds.
+    ///
+    /// # Panics
+    ///   * If the span needs padding and the padded end does not fit in a `Pos`
+    pub const fn pad_to_min_len(&self, min: u64) -> Span {
+        if self.len() >= min {
+            return *self;
+        }
+        Span::from_start_len(self.low, min)
+    }
+
+    /// Returns this span, or an empty span at `low` if `high` is before `low`.
+    ///
+    /// Spans compare and hash by their endpoints alone, so empty spans at the same position are
+    /// already equal however they were made. This additionally collapses reversed spans, which
+    /// can be made with `From<Range<Pos>>`, into that canonical empty span.
+    pub const fn normalize_empty(&self) -> Span {
+        if self.low.0 > self.high.0 {
+            Span {
+                low: self.low,
+                high: self.low,
+            }
+        } else {
+            *self
+        }
+    }
+
     /// Checks whether the span is empty
     pub const fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
+    /// Classifies how `other` is positioned relative to this span.
+    ///
+    /// The variants are checked in the order they are declared in `SpanRelation`, so for example
+    /// an empty span at `self.high()` is `Contains`, not `Adjacent`.
+    pub fn relation(&self, other: Span) -> SpanRelation {
+        if *self == other {
+            SpanRelation::Equal
+        } else if self.contains(other) {
+            SpanRelation::Contains
+        } else if other.contains(*self) {
+            SpanRelation::ContainedBy
+        } else if self.high == other.low || other.high == self.low {
+            SpanRelation::Adjacent
+        } else if self.high < other.low {
+            SpanRelation::Before
+        } else if self.low > other.high {
+            SpanRelation::After
+        } else {
+            SpanRelation::Overlaps
+        }
+    }
+
     /// Create a span that encloses both `self` and `other`.
     pub fn merge(&self, other: Span) -> Span {
         Span {
@@ -90,6 +263,65 @@ impl Span {
             high: cmp::max(self.high, other.high),
         }
     }
+
+    /// Create a span that encloses both `self` and the position `pos`.
+    pub fn including(&self, pos: Pos) -> Span {
+        self.merge(Span {
+            low: pos,
+            high: pos,
+        })
+    }
+
+    /// Attaches a node to this span, equivalent to `Spanned::new(node, self)`.
+    pub fn with_node<T>(self, node: T) -> Spanned<T> {
+        Spanned { node, span: self }
+    }
+
+    /// Create the smallest span that encloses all of `spans`, or `None` if there are none.
+    pub fn enclosing<I: IntoIterator<Item = Span>>(spans: I) -> Option<Span> {
+        spans.into_iter().reduce(|a, b| a.merge(b))
+    }
+}
+
+/// The relationship between two spans, as returned by `Span::relation`.
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+pub enum SpanRelation {
+    /// Both spans have the same bounds.
+    Equal,
+
+    /// `self` strictly contains `other`.
+    Contains,
+
+    /// `other` strictly contains `self`.
+    ContainedBy,
+
+    /// The spans do not overlap but touch, with one ending where the other begins.
+    Adjacent,
+
+    /// `self` ends before `other` begins, with a gap in between.
+    Before,
+
+    /// `self` begins after `other` ends, with a gap in between.
+    After,
+
+    /// The spans partially overlap, with neither containing the other.
+    Overlaps,
+}
+
+/// Gets the span of the text between `a` and `b`, such as the gap between two operands that
+/// should contain an operator.
+///
+/// Returns `None` unless `a` ends at or before the start of `b`. If `a` ends exactly where `b`
+/// starts, the result is an empty span at that position.
+pub fn between(a: Span, b: Span) -> Option<Span> {
+    if a.high <= b.low {
+        Some(Span {
+            low: a.high,
+            high: b.low,
+        })
+    } else {
+        None
+    }
 }
 
 /// Associate a Span with a value of arbitrary type (e.g. an AST node).
@@ -100,6 +332,11 @@ pub struct Spanned<T> {
 }
 
 impl<T> Spanned<T> {
+    /// Associates a node with a span.
+    pub fn new(node: T, span: Span) -> Spanned<T> {
+        Spanned { node, span }
+    }
+
     /// Maps a `Spanned<T>` to `Spanned<U>` by applying the function to the node,
     /// leaving the span untouched.
     pub fn map_node<U, F: FnOnce(T) -> U>(self, op: F) -> Spanned<U> {
@@ -108,6 +345,36 @@ impl<T> Spanned<T> {
             span: self.span,
         }
     }
+
+    /// Keeps the node if it satisfies a predicate, or returns just its span if it doesn't, so
+    /// that the rejected node can still be reported at its location.
+    pub fn filter_node<F: FnOnce(&T) -> bool>(self, pred: F) -> Result<Spanned<T>, Span> {
+        if pred(&self.node) {
+            Ok(self)
+        } else {
+            Err(self.span)
+        }
+    }
+
+    /// Gets just the span of this node, without the node itself.
+    pub fn location(&self) -> Spanned<()> {
+        Spanned {
+            node: (),
+            span: self.span,
+        }
+    }
+
+    /// Collects a list of spanned nodes into one spanned list, whose span encloses all of the
+    /// nodes' spans.
+    ///
+    /// Returns `None` if `items` is empty.
+    pub fn sequence(items: Vec<Spanned<T>>) -> Option<Spanned<Vec<T>>> {
+        let span = Span::enclosing(items.iter().map(|item| item.span))?;
+        Some(Spanned {
+            node: items.into_iter().map(|item| item.node).collect(),
+            span,
+        })
+    }
 }
 
 impl<T> Deref for Spanned<T> {
@@ -117,3 +384,324 @@ impl<T> Deref for Spanned<T> {
         &self.node
     }
 }
+
+impl<T> From<Spanned<T>> for Span {
+    fn from(spanned: Spanned<T>) -> Self {
+        spanned.span
+    }
+}
+
+impl<'a, T> From<&'a Spanned<T>> for Span {
+    fn from(spanned: &'a Spanned<T>) -> Self {
+        spanned.span
+    }
+}
+
+#[test]
+fn test_span_with_endpoints() {
+    let span = Span {
+        low: Pos(5),
+        high: Pos(10),
+    };
+    assert_eq!(span.with_low(Pos(7)), Span::from(Pos(7)..Pos(10)));
+    assert_eq!(span.with_high(Pos(12)), Span::from(Pos(5)..Pos(12)));
+    assert_eq!(span.with_low(Pos(10)).len(), 0);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic]
+fn test_span_with_low_past_high() {
+    let span = Span {
+        low: Pos(5),
+        high: Pos(10),
+    };
+    span.with_low(Pos(11));
+}
+
+#[test]
+fn test_spanned_location() {
+    let spanned = Spanned {
+        node: vec![1, 2, 3],
+        span: Span {
+            low: Pos(3),
+            high: Pos(8),
+        },
+    };
+    let location = spanned.location();
+    assert_eq!(location.span, spanned.span);
+    assert_eq!(location.node, ());
+}
+
+#[test]
+fn test_span_relation() {
+    let span = |low, high| Span {
+        low: Pos(low),
+        high: Pos(high),
+    };
+    let s = span(10, 20);
+    assert_eq!(s.relation(span(10, 20)), SpanRelation::Equal);
+    assert_eq!(s.relation(span(12, 15)), SpanRelation::Contains);
+    assert_eq!(s.relation(span(5, 25)), SpanRelation::ContainedBy);
+    assert_eq!(s.relation(span(20, 25)), SpanRelation::Adjacent);
+    assert_eq!(s.relation(span(5, 10)), SpanRelation::Adjacent);
+    assert_eq!(s.relation(span(22, 25)), SpanRelation::Before);
+    assert_eq!(s.relation(span(2, 8)), SpanRelation::After);
+    assert_eq!(s.relation(span(15, 25)), SpanRelation::Overlaps);
+    assert_eq!(s.relation(span(5, 15)), SpanRelation::Overlaps);
+}
+
+#[test]
+fn test_span_including() {
+    let span = Span {
+        low: Pos(5),
+        high: Pos(10),
+    };
+    assert_eq!(span.including(Pos(2)), Span::from(Pos(2)..Pos(10)));
+    assert_eq!(span.including(Pos(12)), Span::from(Pos(5)..Pos(12)));
+    assert_eq!(span.including(Pos(7)), span);
+    assert_eq!(span.including(Pos(10)), span);
+}
+
+#[test]
+fn test_span_debug() {
+    let span = Span {
+        low: Pos(5),
+        high: Pos(10),
+    };
+    assert_eq!(format!("{:?}", span), "Span(5..10)");
+    assert_eq!(
+        format!("{:?}", Spanned { node: 1, span }),
+        "Spanned { node: 1, span: Span(5..10) }"
+    );
+}
+
+#[test]
+fn test_span_from_start_len() {
+    let span = Span::from_start_len(Pos(5), 3);
+    assert_eq!(span, Span::from(Pos(5)..Pos(8)));
+    assert_eq!(span.len(), 3);
+    assert_eq!(
+        Span::from_start_len(Pos(1), u64::from(u32::MAX) - 1).high(),
+        Pos(u32::MAX)
+    );
+}
+
+#[test]
+#[should_panic(expected = "does not fit")]
+fn test_span_from_start_len_overflow() {
+    Span::from_start_len(Pos(u32::MAX - 2), 3);
+}
+
+#[test]
+fn test_span_pad_to_min_len() {
+    let empty = Span {
+        low: Pos(5),
+        high: Pos(5),
+    };
+    assert_eq!(empty.pad_to_min_len(1), Span::from(Pos(5)..Pos(6)));
+
+    let span = Span::from(Pos(5)..Pos(8));
+    assert_eq!(span.pad_to_min_len(2), span);
+    assert_eq!(span.pad_to_min_len(4), Span::from(Pos(5)..Pos(9)));
+}
+
+#[test]
+#[should_panic(expected = "does not fit")]
+fn test_span_pad_to_min_len_overflow() {
+    Span::from(Pos(5)..Pos(5)).pad_to_min_len(u64::from(u32::MAX) + 2);
+}
+
+#[test]
+fn test_span_contains_pos() {
+    let span = Span {
+        low: Pos(5),
+        high: Pos(8),
+    };
+    assert!(!span.contains_pos(Pos(4)));
+    assert!(span.contains_pos(Pos(5)));
+    assert!(span.contains_pos(Pos(7)));
+    assert!(!span.contains_pos(Pos(8)));
+
+    assert!(!span.contains_pos_inclusive(Pos(4)));
+    assert!(span.contains_pos_inclusive(Pos(5)));
+    assert!(span.contains_pos_inclusive(Pos(8)));
+    assert!(!span.contains_pos_inclusive(Pos(9)));
+
+    let empty = Span {
+        low: Pos(5),
+        high: Pos(5),
+    };
+    assert!(!empty.contains_pos(Pos(5)));
+    assert!(empty.contains_pos_inclusive(Pos(5)));
+}
+
+#[test]
+fn test_spanned_sequence() {
+    let items = vec![
+        Spanned {
+            node: 'b',
+            span: Span {
+                low: Pos(7),
+                high: Pos(8),
+            },
+        },
+        Spanned {
+            node: 'a',
+            span: Span {
+                low: Pos(3),
+                high: Pos(4),
+            },
+        },
+        Spanned {
+            node: 'c',
+            span: Span {
+                low: Pos(10),
+                high: Pos(12),
+            },
+        },
+    ];
+    let sequence = Spanned::sequence(items).unwrap();
+    assert_eq!(sequence.node, ['b', 'a', 'c']);
+    assert_eq!(
+        sequence.span,
+        Span {
+            low: Pos(3),
+            high: Pos(12)
+        }
+    );
+
+    assert!(Spanned::<char>::sequence(vec![]).is_none());
+    assert_eq!(Span::enclosing(vec![]), None);
+}
+
+#[test]
+fn test_span_with_len() {
+    let span = Span::from(Pos(5)..Pos(8));
+    assert_eq!(span.with_len(10), Span::from(Pos(5)..Pos(15)));
+    assert_eq!(span.with_len(1), Span::from(Pos(5)..Pos(6)));
+    assert!(span.with_len(0).is_empty());
+}
+
+#[test]
+#[should_panic(expected = "does not fit")]
+fn test_span_with_len_overflow() {
+    Span::from(Pos(u32::MAX - 2)..Pos(u32::MAX)).with_len(3);
+}
+
+#[test]
+fn test_span_pack_u64() {
+    let spans = [
+        Span::from(Pos(0)..Pos(0)),
+        Span::from(Pos(1)..Pos(5)),
+        Span::from(Pos(1)..Pos(7)),
+        Span::from(Pos(2)..Pos(3)),
+        Span::from(Pos(u32::MAX)..Pos(u32::MAX)),
+    ];
+    for span in &spans {
+        assert_eq!(Span::from_u64(span.to_u64()), *span);
+    }
+    for pair in spans.windows(2) {
+        assert!((pair[0].low(), pair[0].high()) < (pair[1].low(), pair[1].high()));
+        assert!(pair[0].to_u64() < pair[1].to_u64());
+    }
+}
+
+#[test]
+fn test_span_with_node() {
+    let span = Span::from(Pos(3)..Pos(5));
+    assert_eq!(span.with_node(42), Spanned { node: 42, span });
+    assert_eq!(Spanned::new(42, span), Spanned { node: 42, span });
+}
+
+#[test]
+fn test_span_normalize_empty() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    fn hash(span: Span) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        span.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    let from_range = Span::from(Pos(7)..Pos(7));
+    let from_subspan = Span::from(Pos(3)..Pos(10)).subspan(4, 4);
+    assert_eq!(from_range, from_subspan);
+    assert_eq!(hash(from_range), hash(from_subspan));
+    assert_eq!(from_subspan.normalize_empty(), from_range);
+
+    let reversed = Span::from(Pos(7)..Pos(5));
+    assert_eq!(reversed.normalize_empty(), from_range);
+    assert!(reversed.normalize_empty().is_empty());
+
+    let span = Span::from(Pos(5)..Pos(7));
+    assert_eq!(span.normalize_empty(), span);
+}
+
+#[test]
+fn test_between() {
+    let a = Span::from(Pos(2)..Pos(5));
+    let b = Span::from(Pos(8)..Pos(10));
+    assert_eq!(between(a, b), Some(Span::from(Pos(5)..Pos(8))));
+    assert_eq!(between(b, a), None);
+    assert_eq!(
+        between(a, Span::from(Pos(5)..Pos(6))),
+        Some(Span::from(Pos(5)..Pos(5)))
+    );
+    assert_eq!(between(a, Span::from(Pos(4)..Pos(9))), None);
+}
+
+#[test]
+fn test_span_display_string() {
+    let span = Span::from(Pos(5)..Pos(10));
+    assert_eq!(span.to_display_string(), "5..10");
+    assert_eq!(Span::from_display_string("5..10"), Some(span));
+    assert_eq!(
+        Span::from_display_string(&span.to_display_string()),
+        Some(span)
+    );
+
+    for malformed in &[
+        "",
+        "5",
+        "5..",
+        "..10",
+        "5...10",
+        "a..b",
+        "10..5",
+        " 5..10",
+        "5..10..15",
+    ] {
+        assert_eq!(
+            Span::from_display_string(malformed),
+            None,
+            "{:?}",
+            malformed
+        );
+    }
+}
+
+#[test]
+fn test_span_from_spanned() {
+    let span = Span::from(Pos(3)..Pos(8));
+    let spanned = Spanned {
+        node: String::from("node"),
+        span,
+    };
+    let borrowed: Span = (&spanned).into();
+    assert_eq!(borrowed, span);
+    let owned: Span = spanned.into();
+    assert_eq!(owned, span);
+}
+
+#[test]
+fn test_spanned_filter_node() {
+    let span = Span::from(Pos(2)..Pos(4));
+    let spanned = Spanned::new(42u32, span);
+    assert_eq!(
+        spanned.filter_node(|n| n % 2 == 0),
+        Ok(Spanned::new(42, span))
+    );
+    assert_eq!(spanned.filter_node(|n| *n > 100), Err(span));
+}